@@ -0,0 +1,104 @@
+//! Steps wave sized swarms of ants without a window and reports how fast it goes.
+//!
+//! ```text
+//! cargo bench --bench simulation
+//! cargo bench --bench simulation --features parallel
+//! ```
+//!
+//! Each swarm is run twice: once untimed for ticks per second, then again with
+//! [profile] timing on for how long each tick spends in the hot spots.
+//! Times are milliseconds per tick. `for_tile_in_radius` includes `scoring`,
+//! and with `--features parallel` both add up the time spent on every thread.
+
+use std::time::{Duration, Instant};
+
+use ld52::grid_world::TextureView;
+use ld52::level::DEFAULT_WORLD_SIZE;
+use ld52::profile;
+use ld52::simulation::{spawn_ants, AntUpdate, Simulation, TickInput};
+
+const SEED: u64 = 52;
+const ANT_COUNTS: [usize; 3] = [100, 1000, 10_000];
+/// Ticks run before timing starts, so the ants spread out from where they spawned.
+const WARM_UP_TICKS: usize = 60;
+const TIMED_TICKS: usize = 300;
+
+fn main() {
+    println!(
+        "{:>6} {:>8} {:>10} {:>19} {:>8} {:>15} {:>6}",
+        "ants", "update", "ticks/s", "for_tile_in_radius", "scoring", "update_texture", "left"
+    );
+
+    for ant_count in ANT_COUNTS {
+        for ant_update in [AntUpdate::Serial, AntUpdate::Parallel] {
+            profile::set_enabled(false);
+            let mut simulation = swarm(ant_count, ant_update);
+            let elapsed = run(&mut simulation);
+            let ticks_per_second = TIMED_TICKS as f64 / elapsed.as_secs_f64();
+
+            profile::set_enabled(true);
+            let mut simulation = swarm(ant_count, ant_update);
+            profile::take();
+            run(&mut simulation);
+            profile::set_enabled(false);
+            let [for_tile_in_radius, scoring, update_texture] =
+                profile::take().map(|total| per_tick(total.time));
+
+            println!(
+                "{:>6} {:>8} {:>10.1} {:>19.3} {:>8.3} {:>15.3} {:>6}",
+                ant_count,
+                format!("{:?}", ant_update),
+                ticks_per_second,
+                for_tile_in_radius,
+                scoring,
+                update_texture,
+                simulation.ants.len()
+            );
+        }
+    }
+}
+
+/// A fresh world with `ant_count` ants along the top edge like a wave, warmed up.
+fn swarm(ant_count: usize, ant_update: AntUpdate) -> Simulation {
+    let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+    simulation.ant_update = ant_update;
+    // Ants getting to the feet don't hurt anyone or disappear in the sandbox,
+    // so the swarm stays the same size.
+    simulation.sandbox_mode = true;
+    simulation.ants.clear();
+    spawn_ants(
+        &mut simulation.ants,
+        &mut simulation.random,
+        DEFAULT_WORLD_SIZE,
+        ant_count,
+    );
+
+    for _ in 0..WARM_UP_TICKS {
+        tick(&mut simulation);
+    }
+    simulation
+}
+
+fn run(simulation: &mut Simulation) -> Duration {
+    let start = Instant::now();
+    for _ in 0..TIMED_TICKS {
+        tick(simulation);
+    }
+    start.elapsed()
+}
+
+/// A fixed tick, with the texture drawn like the game does after each one.
+fn tick(simulation: &mut Simulation) {
+    simulation.step(&TickInput::default());
+    simulation.grid_world.draw_dirty_chunks(
+        TextureView::Materials,
+        &simulation.materials,
+        |_, _, _, _, pixels| {
+            std::hint::black_box(pixels);
+        },
+    );
+}
+
+fn per_tick(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0 / TIMED_TICKS as f64
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::gamepad::GamepadButton;
+use crate::sandbox::SandboxCommand;
+use crate::simulation::{CurrentItem, PLAYER_HEALTH_MAX};
+
+/// Something the player can do that's bound to keys, the mouse wheel or gamepad buttons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// Picks the item in this hotbar slot, counting from 0.
+    Hotbar(usize),
+    NextItem,
+    PreviousItem,
+    /// Uses the held item, like the left mouse button.
+    Fire,
+    Rummage,
+    ForceWave,
+    EnterSandbox,
+    DebugHurt,
+    Save,
+    Load,
+    CycleView,
+    ToggleDirections,
+    /// Hands over the Inspector and picks it.
+    Inspect,
+    SetHealth(i32),
+    GrantItem(CurrentItem),
+    /// Starts the wave at this index, counting from 0.
+    SkipToWave(i32),
+    /// Only does anything in sandbox mode, like the rest of the sandbox actions.
+    Sandbox(SandboxCommand),
+    NextSandboxTool,
+    Pause,
+    /// Runs one tick while paused.
+    Step,
+}
+
+impl Action {
+    /// Only works in developer mode.
+    pub fn is_cheat(&self) -> bool {
+        matches!(
+            self,
+            Action::ForceWave
+                | Action::EnterSandbox
+                | Action::DebugHurt
+                | Action::Inspect
+                | Action::SetHealth(_)
+                | Action::GrantItem(_)
+                | Action::SkipToWave(_)
+        )
+    }
+}
+
+/// A keyboard key, written by its name in bindings files, like `Key("Space")`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyName(pub Key);
+
+/// The keys that can be named in a bindings file.
+const NAMED_KEYS: &[Key] = &[
+    Key::Digit0,
+    Key::Digit1,
+    Key::Digit2,
+    Key::Digit3,
+    Key::Digit4,
+    Key::Digit5,
+    Key::Digit6,
+    Key::Digit7,
+    Key::Digit8,
+    Key::Digit9,
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::Space,
+];
+
+impl TryFrom<String> for KeyName {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        NAMED_KEYS
+            .iter()
+            .find(|key| format!("{:?}", key) == name)
+            .map(|key| KeyName(*key))
+            .ok_or_else(|| format!("unknown key {:?}", name))
+    }
+}
+
+impl From<KeyName> for String {
+    fn from(key: KeyName) -> Self {
+        format!("{:?}", key.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyName),
+    WheelUp,
+    WheelDown,
+    Gamepad(GamepadButton),
+}
+
+impl Binding {
+    pub fn key(key: Key) -> Self {
+        Binding::Key(KeyName(key))
+    }
+}
+
+/// Which bindings trigger each [Action].
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    actions: Vec<(Action, Vec<Binding>)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut actions = vec![
+            (
+                Action::NextItem,
+                vec![
+                    Binding::WheelDown,
+                    Binding::Gamepad(GamepadButton::RightBumper),
+                ],
+            ),
+            (
+                Action::PreviousItem,
+                vec![
+                    Binding::WheelUp,
+                    Binding::Gamepad(GamepadButton::LeftBumper),
+                ],
+            ),
+            (
+                Action::Fire,
+                vec![Binding::Gamepad(GamepadButton::RightTrigger)],
+            ),
+            (
+                Action::Rummage,
+                vec![
+                    Binding::key(Key::Space),
+                    Binding::Gamepad(GamepadButton::South),
+                ],
+            ),
+            (Action::ForceWave, vec![Binding::key(Key::N)]),
+            (Action::EnterSandbox, vec![Binding::key(Key::K)]),
+            (Action::DebugHurt, vec![Binding::key(Key::R)]),
+            (Action::Save, vec![Binding::key(Key::F5)]),
+            (Action::Load, vec![Binding::key(Key::F9)]),
+            (Action::CycleView, vec![Binding::key(Key::V)]),
+            (Action::ToggleDirections, vec![Binding::key(Key::L)]),
+            (Action::Inspect, vec![Binding::key(Key::I)]),
+            (
+                Action::SetHealth(PLAYER_HEALTH_MAX),
+                vec![Binding::key(Key::H)],
+            ),
+            (
+                Action::GrantItem(CurrentItem::Gun),
+                vec![Binding::key(Key::G)],
+            ),
+            (Action::NextSandboxTool, vec![Binding::key(Key::T)]),
+            (
+                Action::Sandbox(SandboxCommand::NextMaterial),
+                vec![Binding::key(Key::M)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::NextChannel),
+                vec![Binding::key(Key::C)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::NextTargetKind),
+                vec![Binding::key(Key::J)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::Resize(1)),
+                vec![Binding::key(Key::X)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::Resize(-1)),
+                vec![Binding::key(Key::Z)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::SpawnMore(10)),
+                vec![Binding::key(Key::E)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::SpawnMore(-10)),
+                vec![Binding::key(Key::Q)],
+            ),
+            (
+                Action::Sandbox(SandboxCommand::Clear),
+                vec![Binding::key(Key::B)],
+            ),
+            (Action::Pause, vec![Binding::key(Key::P)]),
+            (Action::Step, vec![Binding::key(Key::S)]),
+        ];
+        let hotbar_keys = [
+            Key::Digit1,
+            Key::Digit2,
+            Key::Digit3,
+            Key::Digit4,
+            Key::Digit5,
+            Key::Digit6,
+        ];
+        for (slot, key) in hotbar_keys.into_iter().enumerate() {
+            actions.push((Action::Hotbar(slot), vec![Binding::key(key)]));
+        }
+        Self { actions }
+    }
+}
+
+impl Bindings {
+    /// Loads a RON map from actions to their bindings, like `{ Rummage: [Key("Space")] }`.
+    /// Actions that aren't in the file keep their default bindings.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&source)
+    }
+
+    /// Like [Bindings::load] for the contents of a file.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let overrides: HashMap<Action, Vec<Binding>> =
+            ron::from_str(source).map_err(|e| e.to_string())?;
+
+        let mut bindings = Self::default();
+        for (action, action_bindings) in overrides {
+            match bindings.actions.iter_mut().find(|(a, _)| *a == action) {
+                Some((_, existing)) => *existing = action_bindings,
+                None => bindings.actions.push((action, action_bindings)),
+            }
+        }
+        bindings.check()?;
+        Ok(bindings)
+    }
+
+    /// Errors if a binding would trigger more than one action.
+    pub fn check(&self) -> Result<(), String> {
+        let mut seen: Vec<(Binding, Action)> = Vec::new();
+        let mut duplicates = Vec::new();
+        for (action, bindings) in &self.actions {
+            for binding in bindings {
+                match seen.iter().find(|(b, _)| b == binding) {
+                    Some((_, other)) => duplicates.push(format!(
+                        "{:?} is bound to both {:?} and {:?}",
+                        binding, other, action
+                    )),
+                    None => seen.push((*binding, *action)),
+                }
+            }
+        }
+
+        if duplicates.is_empty() {
+            Ok(())
+        } else {
+            Err(duplicates.join("\n"))
+        }
+    }
+
+    /// The action `binding` triggers, if any.
+    pub fn action(&self, binding: Binding) -> Option<Action> {
+        self.actions
+            .iter()
+            .find(|(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.actions
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map_or(&[], |(_, bindings)| bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_bindings_load() {
+        let bindings = Bindings::parse(include_str!("../assets/bindings.ron")).unwrap();
+        bindings.check().unwrap();
+        assert_eq!(
+            bindings.action(Binding::key(Key::Space)),
+            Some(Action::Rummage)
+        );
+    }
+
+    #[test]
+    fn overrides_keep_the_other_defaults() {
+        let bindings = Bindings::parse(r#"{ Rummage: [Key("F1")] }"#).unwrap();
+        assert_eq!(bindings.bindings(Action::Rummage), &[Binding::key(Key::F1)]);
+        assert_eq!(bindings.action(Binding::key(Key::Space)), None);
+        assert_eq!(bindings.bindings(Action::Save), &[Binding::key(Key::F5)]);
+        assert_eq!(
+            bindings.action(Binding::Gamepad(GamepadButton::RightBumper)),
+            Some(Action::NextItem)
+        );
+    }
+
+    #[test]
+    fn duplicates_name_both_actions() {
+        // N is already bound to ForceWave.
+        let error = Bindings::parse(r#"{ Save: [Key("N")] }"#).unwrap_err();
+        assert!(error.contains("ForceWave"), "{}", error);
+        assert!(error.contains("Save"), "{}", error);
+    }
+}
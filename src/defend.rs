@@ -0,0 +1,101 @@
+//! What the ants are after and the player has to protect, like their feet.
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+/// One box per foot.
+const FEET_SHOT_BOXES: [ShotBox; 2] = [
+    ShotBox {
+        offset: Vec2::new(-5.0, 22.0),
+        half_size: Vec2::new(5.0, 13.0),
+    },
+    ShotBox {
+        offset: Vec2::new(15.5, 21.0),
+        half_size: Vec2::new(5.0, 13.0),
+    },
+];
+const SANDWICH_SHOT_BOXES: [ShotBox; 1] = [ShotBox {
+    offset: Vec2::ZERO,
+    half_size: Vec2::new(6.0, 4.0),
+}];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DefendTargetKind {
+    Feet,
+    Sandwich,
+}
+
+impl DefendTargetKind {
+    pub const ALL: [DefendTargetKind; 2] = [DefendTargetKind::Feet, DefendTargetKind::Sandwich];
+
+    /// How close an ant has to get to hurt the player.
+    pub fn reach_radius(self) -> f32 {
+        match self {
+            DefendTargetKind::Feet => 10.0,
+            DefendTargetKind::Sandwich => 6.0,
+        }
+    }
+
+    /// The parts that hurt the player when they're shot.
+    pub fn shot_boxes(self) -> &'static [ShotBox] {
+        match self {
+            DefendTargetKind::Feet => &FEET_SHOT_BOXES,
+            DefendTargetKind::Sandwich => &SANDWICH_SHOT_BOXES,
+        }
+    }
+
+    /// How much health the player loses when it's shot.
+    pub fn shot_damage(self) -> i32 {
+        match self {
+            DefendTargetKind::Feet => 10,
+            DefendTargetKind::Sandwich => 5,
+        }
+    }
+}
+
+/// A rectangle relative to a [DefendTarget]'s position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShotBox {
+    pub offset: Vec2,
+    pub half_size: Vec2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DefendTarget {
+    pub kind: DefendTargetKind,
+    /// Where the ants head for.
+    #[serde(with = "crate::file_format::vec2")]
+    pub position: Vec2,
+}
+
+impl DefendTarget {
+    pub fn new(kind: DefendTargetKind, position: Vec2) -> Self {
+        Self { kind, position }
+    }
+
+    /// The player's feet at the bottom middle of the world, where they've always been.
+    pub fn default_feet(world_size: Vec2) -> Self {
+        Self::new(DefendTargetKind::Feet, Vec2::new(world_size.x / 2.0, 15.0))
+    }
+
+    /// Ants come at it from above, so ones that are past it don't count.
+    pub fn reached_by(&self, position: Vec2) -> bool {
+        position.y > self.position.y
+            && (position - self.position).length() < self.kind.reach_radius()
+    }
+
+    pub fn is_hit_by_shot(&self, position: Vec2) -> bool {
+        self.kind.shot_boxes().iter().any(|shot_box| {
+            let distance = (position - (self.position + shot_box.offset)).abs();
+            distance.x < shot_box.half_size.x && distance.y < shot_box.half_size.y
+        })
+    }
+}
+
+/// How far `position` is from the closest target, `None` if there are none.
+pub fn distance_to_closest(targets: &[DefendTarget], position: Vec2) -> Option<f32> {
+    targets
+        .iter()
+        .map(|target| (position - target.position).length())
+        .min_by(|a, b| a.total_cmp(b))
+}
@@ -0,0 +1,152 @@
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::grid_world::Tile;
+use crate::simulation::Ant;
+
+/// Which [EnemyKind] in the [EnemyRegistry] an enemy is.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct EnemyKindId(pub usize);
+
+impl EnemyKindId {
+    pub const ANT: EnemyKindId = EnemyKindId(0);
+    pub const SPIDER: EnemyKindId = EnemyKindId(1);
+}
+
+/// Adjusts how much an enemy wants to move to a tile. Lower scores win.
+/// `score` is what a regular ant would give the tile.
+pub type ScoreFn = fn(enemy: &Ant, tile_position: Vec2, tile: &Tile, score: i32) -> i32;
+
+pub struct EnemyKind {
+    pub name: String,
+    pub sprite: String,
+    pub sprite_color: Color,
+    /// The range its speed is picked from when it spawns.
+    pub speed: (f32, f32),
+    /// Applied to its speed every tick.
+    pub speed_multiplier: f32,
+    pub health: u8,
+    pub score: ScoreFn,
+    /// Picks up materials that are [carried](crate::materials::MaterialBehavior::carried)
+    /// and takes them back to the spawn edge.
+    pub carries: bool,
+    /// Lays pheromones and wears down the tiles it walks over.
+    pub forages: bool,
+    pub killed_by_kill_flag: bool,
+    pub killed_by_kill_ants_only: bool,
+    /// Makes tiles in this radius deadly to ants.
+    pub kills_ants_in_radius: Option<f32>,
+    /// Chases the nearest enemy within this radius that doesn't hunt itself.
+    pub hunts_in_radius: Option<f32>,
+    /// How strongly it steers away from enemies right next to it.
+    pub crowd_avoidance: f32,
+    /// How much it follows the [FlowField](crate::flow_field::FlowField) to the defend targets
+    /// rather than its usual scoring, from 0 to 1.
+    pub flow_field_weight: f32,
+}
+
+impl EnemyKind {
+    pub fn ant() -> Self {
+        Self {
+            name: "ant".into(),
+            sprite: "assets/AntFrame1.png".into(),
+            sprite_color: Color::BLACK,
+            speed: (0.05, 0.2),
+            speed_multiplier: 1.0,
+            health: 255,
+            score: |_, _, _, score| score,
+            carries: true,
+            forages: true,
+            killed_by_kill_flag: true,
+            killed_by_kill_ants_only: true,
+            kills_ants_in_radius: None,
+            hunts_in_radius: None,
+            crowd_avoidance: 0.3,
+            flow_field_weight: 0.0,
+        }
+    }
+
+    /// Marches on the defend targets, going around barriers instead of getting stuck on them.
+    /// It doesn't stop for food.
+    pub fn army_ant() -> Self {
+        Self {
+            name: "army_ant".into(),
+            sprite: "assets/AntFrame1.png".into(),
+            sprite_color: Color::from_srgb_hex(0x5A1A0A, 1.0),
+            speed: (0.1, 0.2),
+            carries: false,
+            forages: false,
+            flow_field_weight: 0.8,
+            ..Self::ant()
+        }
+    }
+
+    /// Spiders are the player's. They wander around where they were dropped and eat ants.
+    pub fn spider() -> Self {
+        Self {
+            name: "spider".into(),
+            sprite: "assets/spider.png".into(),
+            sprite_color: Color::RED,
+            speed: (0.05, 0.2),
+            speed_multiplier: 5.0,
+            health: 255,
+            score: |spider, tile_position, tile, _| {
+                let distance_from_home = (tile_position - spider.home).length();
+                if distance_from_home > 50.0 {
+                    ((distance_from_home / 30.0) * 100.0) as i32
+                } else {
+                    -(tile.times_changed as i32)
+                }
+            },
+            carries: false,
+            forages: false,
+            killed_by_kill_flag: true,
+            killed_by_kill_ants_only: false,
+            kills_ants_in_radius: Some(10.0),
+            hunts_in_radius: Some(50.0),
+            crowd_avoidance: 0.0,
+            flow_field_weight: 0.0,
+        }
+    }
+}
+
+pub struct EnemyRegistry {
+    kinds: Vec<EnemyKind>,
+}
+
+impl EnemyRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self { kinds: Vec::new() };
+        registry.register(EnemyKind::ant());
+        registry.register(EnemyKind::spider());
+        registry.register(EnemyKind::army_ant());
+        registry
+    }
+
+    pub fn register(&mut self, kind: EnemyKind) -> EnemyKindId {
+        self.kinds.push(kind);
+        EnemyKindId(self.kinds.len() - 1)
+    }
+
+    pub fn get(&self, id: EnemyKindId) -> &EnemyKind {
+        &self.kinds[id.0]
+    }
+
+    pub fn find(&self, name: &str) -> Option<EnemyKindId> {
+        self.kinds
+            .iter()
+            .position(|kind| kind.name == name)
+            .map(EnemyKindId)
+    }
+
+    pub fn uses_flow_field(&self, id: EnemyKindId) -> bool {
+        self.get(id).flow_field_weight > 0.0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EnemyKindId, &EnemyKind)> {
+        self.kinds
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| (EnemyKindId(i), kind))
+    }
+}
@@ -0,0 +1,89 @@
+//! What saves, levels and recordings have in common: they're RON, and start with a version.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Every file starts with its version, so older ones can be told apart.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    contents: T,
+}
+
+/// `contents` as RON on a single line, after `version`.
+pub(crate) fn to_ron<T: Serialize>(version: u32, contents: &T) -> String {
+    ron::to_string(&Envelope { version, contents }).unwrap()
+}
+
+/// Reads what [to_ron] wrote. Other versions are refused rather than read wrong,
+/// `what` is what the file is called in the error, like "save".
+pub(crate) fn from_ron<T: DeserializeOwned>(
+    source: &str,
+    version: u32,
+    what: &str,
+) -> std::io::Result<T> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    let Version { version: found } =
+        ron::from_str(source).map_err(|e| invalid_data(format!("not a {}: {}", what, e)))?;
+    if found != version {
+        return Err(invalid_data(format!(
+            "{} is version {} but only version {} can be loaded",
+            what, found, version
+        )));
+    }
+
+    let envelope: Envelope<T> = ron::from_str(source).map_err(|e| invalid_data(e.to_string()))?;
+    Ok(envelope.contents)
+}
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// For `#[serde(with = "crate::file_format::vec2")]`, koi3's vectors can't be serialized themselves.
+pub(crate) mod vec2 {
+    use koi3::Vec2;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        let [x, y] = <[f32; 2]>::deserialize(deserializer)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+/// Like [vec2] for a list of them.
+pub(crate) mod vec2s {
+    use koi3::Vec2;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &[Vec2], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(v.iter().map(|v| [v.x, v.y]))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec2>, D::Error> {
+        let points = Vec::<[f32; 2]>::deserialize(deserializer)?;
+        Ok(points.into_iter().map(|[x, y]| Vec2::new(x, y)).collect())
+    }
+}
+
+/// Like [vec2] for `Vec3`s.
+pub(crate) mod vec3 {
+    use koi3::Vec3;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+        [v.x, v.y, v.z].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec3, D::Error> {
+        let [x, y, z] = <[f32; 3]>::deserialize(deserializer)?;
+        Ok(Vec3::new(x, y, z))
+    }
+}
@@ -0,0 +1,288 @@
+//! How far every tile is from the closest [DefendTarget], walking around whatever's in the way.
+//! Enemy kinds with a [flow_field_weight](crate::enemies::EnemyKind::flow_field_weight)
+//! head downhill in it, which routes them around barriers instead of into them.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use koi3::*;
+
+use crate::defend::DefendTarget;
+use crate::grid_world::{GridWorld, Tile, TileMaterial};
+use crate::materials::MaterialRegistry;
+
+/// The distance of tiles there's no way to a target from.
+pub const UNREACHABLE: u32 = u32::MAX;
+/// Tiles higher than this can't be walked on, like the ants' height veto.
+const WALL_HEIGHT: u8 = 230;
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+/// Added for each step of height climbed.
+const CLIMB_COST: u32 = 1;
+const NO_PARENT: u32 = u32::MAX;
+
+pub struct FlowField {
+    width: usize,
+    height: usize,
+    /// Indexed like [GridWorld::tiles]. The cost of walking from each tile to the closest target.
+    pub distance: Vec<u32>,
+    /// The neighbour each tile's shortest path goes through next.
+    parent: Vec<u32>,
+    /// What each tile was when the distances were worked out, to spot what's changed since.
+    seen: Vec<(u8, TileMaterial)>,
+    /// Scratch space for [FlowField::update], all false between updates.
+    forgotten: Vec<bool>,
+    targets: Vec<DefendTarget>,
+    queue: BinaryHeap<Reverse<(u32, u32)>>,
+}
+
+impl FlowField {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            distance: Vec::new(),
+            parent: Vec::new(),
+            seen: Vec::new(),
+            forgotten: Vec::new(),
+            targets: Vec::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// The distance at a world position, [UNREACHABLE] outside the world.
+    pub fn distance_at(&self, position: Vec2) -> u32 {
+        if position.x < 0.0 || position.y < 0.0 {
+            return UNREACHABLE;
+        }
+        let (x, y) = (position.x as usize, position.y as usize);
+        if x >= self.width || y >= self.height {
+            return UNREACHABLE;
+        }
+        self.distance[x * self.height + y]
+    }
+
+    /// Brings the distances up to date with the tiles.
+    /// Only the tiles whose shortest path went through a changed tile are worked out again,
+    /// unless the targets moved or the world is new. What's changed is found with
+    /// [GridWorld::take_changed_tiles], so nothing else should take them.
+    pub fn update(
+        &mut self,
+        grid_world: &mut GridWorld,
+        materials: &MaterialRegistry,
+        targets: &[DefendTarget],
+    ) {
+        let maybe_changed = grid_world.take_changed_tiles();
+        if (self.width, self.height) != (grid_world.width, grid_world.height)
+            || self.targets != targets
+        {
+            self.recompute(grid_world, materials, targets);
+            return;
+        }
+
+        let mut changed = Vec::new();
+        let tiles = &grid_world.tiles;
+        let mut check = |i: usize| {
+            let now = (tiles[i].height, tiles[i].material);
+            if self.seen[i] != now {
+                self.seen[i] = now;
+                changed.push(i);
+            }
+        };
+        match maybe_changed {
+            Some(maybe_changed) => maybe_changed.into_iter().for_each(&mut check),
+            None => (0..tiles.len()).for_each(&mut check),
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        // Everything downstream of a changed tile might have got further away,
+        // so it's forgotten and worked out again from its neighbours.
+        let mut invalid = Vec::new();
+        let mut stack = changed;
+        while let Some(i) = stack.pop() {
+            if self.forgotten[i] {
+                continue;
+            }
+            self.forgotten[i] = true;
+            invalid.push(i);
+            self.distance[i] = UNREACHABLE;
+            self.parent[i] = NO_PARENT;
+            for (n, _) in neighbours(self.width, self.height, i) {
+                if self.parent[n] == i as u32 {
+                    stack.push(n);
+                }
+            }
+        }
+
+        for &i in invalid.iter() {
+            self.forgotten[i] = false;
+            if self.is_target(i) {
+                self.distance[i] = 0;
+                self.queue.push(Reverse((0, i as u32)));
+                continue;
+            }
+
+            let tiles = &grid_world.tiles;
+            let mut best = UNREACHABLE;
+            let mut best_parent = NO_PARENT;
+            for (n, diagonal) in neighbours(self.width, self.height, i) {
+                if self.distance[n] == UNREACHABLE {
+                    continue;
+                }
+                let Some(cost) = step_cost(&tiles[i], &tiles[n], diagonal, materials) else {
+                    continue;
+                };
+                let distance = self.distance[n].saturating_add(cost);
+                if distance < best {
+                    best = distance;
+                    best_parent = n as u32;
+                }
+            }
+            if best != UNREACHABLE {
+                self.distance[i] = best;
+                self.parent[i] = best_parent;
+                self.queue.push(Reverse((best, i as u32)));
+            }
+        }
+
+        self.propagate(grid_world, materials);
+    }
+
+    fn recompute(
+        &mut self,
+        grid_world: &GridWorld,
+        materials: &MaterialRegistry,
+        targets: &[DefendTarget],
+    ) {
+        self.width = grid_world.width;
+        self.height = grid_world.height;
+        self.targets = targets.to_vec();
+        let len = self.width * self.height;
+        self.distance = vec![UNREACHABLE; len];
+        self.parent = vec![NO_PARENT; len];
+        self.forgotten = vec![false; len];
+        self.seen = grid_world
+            .tiles
+            .iter()
+            .map(|t| (t.height, t.material))
+            .collect();
+        self.queue.clear();
+
+        for i in 0..len {
+            if self.is_target(i) {
+                self.distance[i] = 0;
+                self.queue.push(Reverse((0, i as u32)));
+            }
+        }
+        self.propagate(grid_world, materials);
+    }
+
+    /// Dijkstra's algorithm, carrying on from whatever's queued.
+    fn propagate(&mut self, grid_world: &GridWorld, materials: &MaterialRegistry) {
+        let tiles = &grid_world.tiles;
+        while let Some(Reverse((distance, i))) = self.queue.pop() {
+            let i = i as usize;
+            if distance > self.distance[i] {
+                // A shorter way here was found after this was queued.
+                continue;
+            }
+
+            for (n, diagonal) in neighbours(self.width, self.height, i) {
+                // The step is from the neighbour onto this tile, towards the target.
+                let Some(cost) = step_cost(&tiles[n], &tiles[i], diagonal, materials) else {
+                    continue;
+                };
+                let through_here = distance.saturating_add(cost);
+                if through_here < self.distance[n] {
+                    self.distance[n] = through_here;
+                    self.parent[n] = i as u32;
+                    self.queue.push(Reverse((through_here, n as u32)));
+                }
+            }
+        }
+    }
+
+    fn is_target(&self, i: usize) -> bool {
+        let (x, y) = (i / self.height, i % self.height);
+        self.targets.iter().any(|target| {
+            target.position.x.max(0.0) as usize == x && target.position.y.max(0.0) as usize == y
+        })
+    }
+}
+
+/// The tiles around tile `i`, and whether they're diagonal from it.
+fn neighbours(width: usize, height: usize, i: usize) -> impl Iterator<Item = (usize, bool)> {
+    let (x, y) = ((i / height) as isize, (i % height) as isize);
+    (-1..=1isize)
+        .flat_map(|dx| (-1..=1isize).map(move |dy| (dx, dy)))
+        .filter(|d| *d != (0, 0))
+        .filter_map(move |(dx, dy)| {
+            let (nx, ny) = (x + dx, y + dy);
+            let inside = nx >= 0 && ny >= 0 && nx < width as isize && ny < height as isize;
+            inside.then(|| (nx as usize * height + ny as usize, dx != 0 && dy != 0))
+        })
+}
+
+/// What it costs to step from `from` onto its neighbour `to`, `None` if it can't be done.
+fn step_cost(from: &Tile, to: &Tile, diagonal: bool, materials: &MaterialRegistry) -> Option<u32> {
+    if to.height > WALL_HEIGHT {
+        return None;
+    }
+    let walk_cost = materials.get(to.material).walk_cost?;
+    let climb = to.height.saturating_sub(from.height) as u32 * CLIMB_COST;
+    let step = if diagonal {
+        DIAGONAL_COST
+    } else {
+        STRAIGHT_COST
+    };
+    Some(step + climb + walk_cost.max(0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defend::DefendTargetKind;
+
+    #[test]
+    fn updating_matches_working_it_all_out_again() {
+        let materials = MaterialRegistry::new();
+        let targets = [
+            DefendTarget::new(DefendTargetKind::Feet, Vec2::new(20.0, 5.0)),
+            DefendTarget::new(DefendTargetKind::Sandwich, Vec2::new(50.0, 10.0)),
+        ];
+        let mut random = Random::new_with_seed(23);
+        let mut grid_world = GridWorld::new(64, 80);
+        for t in grid_world.tiles.iter_mut() {
+            t.height = random.range_u32(100..200) as u8;
+        }
+        let mut flow_field = FlowField::new();
+        flow_field.update(&mut grid_world, &materials, &targets);
+
+        for step in 0..200 {
+            let center = Vec2::new(random.range_f32(0.0..64.0), random.range_f32(0.0..80.0));
+            let radius = random.range_f32(1.0..6.0);
+            let change = random.range_u32(0..4);
+            grid_world.for_tile_in_radius(center, radius, |_, _, t| {
+                match change {
+                    // Walls and slow water come and go.
+                    0 => t.height = 255,
+                    1 => t.height = 100,
+                    2 => t.material = TileMaterial::Water,
+                    _ => t.material = TileMaterial::Earth,
+                }
+                false
+            });
+            flow_field.update(&mut grid_world, &materials, &targets);
+
+            let mut from_scratch = FlowField::new();
+            from_scratch.update(&mut grid_world, &materials, &targets);
+            assert!(
+                flow_field.distance == from_scratch.distance,
+                "the distances differ after change {}",
+                step
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use gilrs::{Axis, Button, EventType, Gilrs};
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::warn;
+
+/// Stick movement smaller than this is ignored.
+const STICK_DEADZONE: f32 = 0.2;
+
+/// Buttons named the way they're laid out, not by what's printed on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl GamepadButton {
+    fn from_gilrs(button: Button) -> Option<Self> {
+        Some(match button {
+            Button::South => GamepadButton::South,
+            Button::East => GamepadButton::East,
+            Button::West => GamepadButton::West,
+            Button::North => GamepadButton::North,
+            Button::LeftTrigger => GamepadButton::LeftBumper,
+            Button::RightTrigger => GamepadButton::RightBumper,
+            Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+            Button::RightTrigger2 => GamepadButton::RightTrigger,
+            Button::Select => GamepadButton::Select,
+            Button::Start => GamepadButton::Start,
+            Button::DPadUp => GamepadButton::DPadUp,
+            Button::DPadDown => GamepadButton::DPadDown,
+            Button::DPadLeft => GamepadButton::DPadLeft,
+            Button::DPadRight => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+/// Every connected gamepad, read as if they were one.
+pub struct Gamepads {
+    /// `None` if gamepads aren't supported here.
+    gilrs: Option<Gilrs>,
+    pressed: Vec<GamepadButton>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                warn!("Gamepads aren't available: {}", e);
+                None
+            }
+        };
+        Self {
+            gilrs,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Buttons pressed (`true`) and released (`false`) since the last poll.
+    pub fn poll(&mut self) -> Vec<(GamepadButton, bool)> {
+        let mut changes = Vec::new();
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return changes;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            let (button, pressed) = match event.event {
+                EventType::ButtonPressed(button, _) => (button, true),
+                EventType::ButtonReleased(button, _) => (button, false),
+                _ => continue,
+            };
+            let Some(button) = GamepadButton::from_gilrs(button) else {
+                continue;
+            };
+
+            if pressed {
+                self.pressed.push(button);
+            } else if let Some(i) = self.pressed.iter().position(|b| *b == button) {
+                self.pressed.swap_remove(i);
+            }
+            changes.push((button, pressed));
+        }
+        changes
+    }
+
+    pub fn is_pressed(&self, button: GamepadButton) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    /// The left stick of whichever gamepad is pushing theirs furthest, with up as +Y.
+    pub fn left_stick(&self) -> Vec2 {
+        let Some(gilrs) = self.gilrs.as_ref() else {
+            return Vec2::ZERO;
+        };
+
+        let mut stick = Vec2::ZERO;
+        for (_, gamepad) in gilrs.gamepads() {
+            let s = Vec2::new(
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            );
+            if s.length() > stick.length() {
+                stick = s;
+            }
+        }
+
+        if stick.length() < STICK_DEADZONE {
+            Vec2::ZERO
+        } else {
+            stick
+        }
+    }
+}
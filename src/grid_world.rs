@@ -0,0 +1,1178 @@
+use std::collections::VecDeque;
+
+use koi3::*;
+use koi_graphics_context::{FilterMode, TextureSettings};
+use serde::{Deserialize, Serialize};
+
+use crate::defend::{self, DefendTarget};
+use crate::materials::MaterialRegistry;
+use crate::profile::{self, Section};
+
+/// High enough that ants won't walk there.
+pub const PHEROMONE_MAX: f32 = i16::MAX as f32;
+
+/// The most height a tile can slump into a neighbour in one tick.
+const SLUMP_PER_TICK: u8 = 4;
+
+/// The texture is uploaded in squares of this many tiles.
+const TEXTURE_CHUNK_SIZE: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PheromoneChannel {
+    /// Laid by ants heading out, followed by ants heading back.
+    /// It also holds the gradient that leads ants to the [DefendTarget]s.
+    Home,
+    /// Laid by ants carrying something back and given off by chip crumbs.
+    /// Ants heading out follow it.
+    Food,
+    /// Left where ants die. Draws more ants in.
+    Alarm,
+}
+
+impl PheromoneChannel {
+    pub const ALL: [PheromoneChannel; 3] = [
+        PheromoneChannel::Home,
+        PheromoneChannel::Food,
+        PheromoneChannel::Alarm,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Pheromones {
+    pub home: f32,
+    pub food: f32,
+    pub alarm: f32,
+}
+
+impl Pheromones {
+    pub fn get(&self, channel: PheromoneChannel) -> f32 {
+        match channel {
+            PheromoneChannel::Home => self.home,
+            PheromoneChannel::Food => self.food,
+            PheromoneChannel::Alarm => self.alarm,
+        }
+    }
+
+    pub fn get_mut(&mut self, channel: PheromoneChannel) -> &mut f32 {
+        match channel {
+            PheromoneChannel::Home => &mut self.home,
+            PheromoneChannel::Food => &mut self.food,
+            PheromoneChannel::Alarm => &mut self.alarm,
+        }
+    }
+}
+
+/// How one pheromone channel spreads out and fades each tick.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PheromoneRates {
+    /// How much of the difference from its neighbours' average a tile loses each tick, 0 to 1.
+    pub diffusion: f32,
+    /// How much of the trail on a tile fades each tick, 0 to 1.
+    pub evaporation: f32,
+}
+
+/// Only the difference from the level a tile was last reset to spreads and fades,
+/// so the gradients set up by `reset_pheromones` and friends stay put.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PheromoneConfig {
+    pub home: PheromoneRates,
+    pub food: PheromoneRates,
+    pub alarm: PheromoneRates,
+}
+
+impl Default for PheromoneConfig {
+    fn default() -> Self {
+        Self {
+            home: PheromoneRates {
+                diffusion: 0.02,
+                evaporation: 0.001,
+            },
+            food: PheromoneRates {
+                diffusion: 0.05,
+                evaporation: 0.005,
+            },
+            alarm: PheromoneRates {
+                diffusion: 0.1,
+                evaporation: 0.01,
+            },
+        }
+    }
+}
+
+impl PheromoneConfig {
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&source).map_err(|e| e.to_string())
+    }
+
+    pub fn rates(&self, channel: PheromoneChannel) -> PheromoneRates {
+        match channel {
+            PheromoneChannel::Home => self.home,
+            PheromoneChannel::Food => self.food,
+            PheromoneChannel::Alarm => self.alarm,
+        }
+    }
+}
+
+/// The most octaves of noise the terrain generator can layer.
+pub const MAX_OCTAVES: usize = 8;
+
+/// How [GridWorld::initial_terrain_setup] shapes the world.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainConfig {
+    /// How many tiles across a hill is, roughly.
+    pub noise_scale: f32,
+    /// Layers of finer and finer detail in the height. Up to [MAX_OCTAVES].
+    pub height_octaves: usize,
+    /// Layers of detail in where grass, rocks and water go. Up to [MAX_OCTAVES].
+    pub material_octaves: usize,
+    /// How far the lowest tiles are below the highest, which are always 255.
+    pub height_range: f32,
+    /// Grass grows where the material noise is above this, 0 to 1.
+    pub grass_threshold: f32,
+    /// Rocks are too high for ants to climb. They go where a separate noise is above this.
+    pub rock_threshold: Option<f32>,
+    /// Water fills the lowest tiles, where the height noise is below this, 0 to 1.
+    pub water_level: Option<f32>,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Biome::Garden.terrain_config()
+    }
+}
+
+/// Named [TerrainConfig]s that waves can pick between.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Biome {
+    /// Flat, with puddles of spilled water.
+    KitchenFloor,
+    /// Hills and patches of grass. This is what the game has always used.
+    #[default]
+    Garden,
+    /// Gentle dunes with rocks poking out of them.
+    Sandbox,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 3] = [Biome::KitchenFloor, Biome::Garden, Biome::Sandbox];
+
+    pub fn terrain_config(self) -> TerrainConfig {
+        match self {
+            Biome::KitchenFloor => TerrainConfig {
+                noise_scale: 20.0,
+                height_octaves: 2,
+                material_octaves: 4,
+                height_range: 20.0,
+                grass_threshold: 1.0,
+                rock_threshold: None,
+                water_level: Some(0.3),
+            },
+            Biome::Garden => TerrainConfig {
+                noise_scale: 30.0,
+                height_octaves: 8,
+                material_octaves: 4,
+                height_range: 150.0,
+                grass_threshold: 0.8,
+                rock_threshold: None,
+                water_level: None,
+            },
+            Biome::Sandbox => TerrainConfig {
+                noise_scale: 40.0,
+                height_octaves: 4,
+                material_octaves: 4,
+                height_range: 60.0,
+                grass_threshold: 1.0,
+                rock_threshold: Some(0.8),
+                water_level: None,
+            },
+        }
+    }
+}
+
+/// A read only look at a [GridWorld]'s tiles. Unlike the world it can be shared between threads.
+#[derive(Clone, Copy)]
+pub struct TileView<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// Indexed like [GridWorld::tiles].
+    pub tiles: &'a [Tile],
+}
+
+impl<'a> TileView<'a> {
+    pub fn get_tile(&self, x: f32, y: f32) -> Option<&'a Tile> {
+        self.tiles.get(tile_index(self.width, self.height, x, y)?)
+    }
+
+    /// Like [GridWorld::for_tile_in_radius], visiting the same tiles in the same order.
+    pub fn for_tile_in_radius(
+        &self,
+        center: Vec2,
+        radius: f32,
+        mut f: impl FnMut(Vec2, f32, &Tile),
+    ) {
+        let _timer = profile::timer(Section::ForTileInRadius);
+        let (min, max) = radius_bounds(self.width, self.height, center, radius);
+
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                let t = Vec2::new(x as f32, y as f32);
+                let distance = (t - center).length();
+                if distance < radius {
+                    if let Some(tile) = self.tiles.get(x * self.height + y) {
+                        f(t, distance, tile);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The index in [GridWorld::tiles] of the tile at a world position, `None` outside the world.
+fn tile_index(width: usize, height: usize, x: f32, y: f32) -> Option<usize> {
+    if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+        return None;
+    }
+    Some(x as usize * height + y as usize)
+}
+
+/// The tile coordinates, from inclusive to exclusive, of the square around a circle,
+/// cut off at the edges of the world.
+fn radius_bounds(
+    width: usize,
+    height: usize,
+    center: Vec2,
+    radius: f32,
+) -> (Vector<usize, 2>, Vector<usize, 2>) {
+    let min: Matrix<f32, 2, 1> = center - Vec2::fill(radius);
+    let max: Matrix<f32, 2, 1> = center + Vec2::fill(radius);
+    let max = max.as_usize().min(Vector::<_, 2>::new(width, height));
+    (min.as_usize(), max)
+}
+
+pub struct GridWorld {
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<Tile>,
+    pub pheromone_config: PheromoneConfig,
+    /// What each tile's home scent fades back to. The other channels fade to nothing.
+    pub pheromone_baseline: Vec<f32>,
+    /// Whether [GridWorld::update_tiles] lets materials slump, spread and settle.
+    /// Off unless asked for, so the terrain stays how it's always been.
+    pub tile_physics: bool,
+    texture_scratch: Vec<[f32; 4]>,
+    texture: Option<GridTexture>,
+    /// Which chunks of the texture have tiles that changed since it was last drawn.
+    dirty_chunks: Vec<bool>,
+    /// Tiles whose height or material might have changed since [GridWorld::take_changed_tiles],
+    /// `None` if it could be any of them.
+    changed_tiles: Option<Vec<usize>>,
+    tiles_to_update: VecDeque<usize>,
+    pheromone_scratch: PheromoneScratch,
+}
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Tile {
+    pub height: u8,
+    pub material: TileMaterial,
+    pub pheromones: Pheromones,
+    pub kill_flag: bool,
+    pub kill_ants_only: bool,
+    pub times_changed: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum TileMaterial {
+    Earth,
+    Grass,
+    AntGuts,
+    AntBody,
+    ToothPaste1,
+    ToothPaste2,
+    ToothPaste3,
+    Nacho,
+    Water,
+    /// What toothpaste turns into once it's stopped spreading.
+    DriedToothPaste,
+    Debug,
+}
+
+impl TileMaterial {
+    /// In the order they're declared in, so `material as usize` indexes it.
+    pub const ALL: [TileMaterial; 11] = [
+        TileMaterial::Earth,
+        TileMaterial::Grass,
+        TileMaterial::AntGuts,
+        TileMaterial::AntBody,
+        TileMaterial::ToothPaste1,
+        TileMaterial::ToothPaste2,
+        TileMaterial::ToothPaste3,
+        TileMaterial::Nacho,
+        TileMaterial::Water,
+        TileMaterial::DriedToothPaste,
+        TileMaterial::Debug,
+    ];
+}
+
+/// What [GridWorld::update_texture] draws. Everything but [TextureView::Materials] is for debugging.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextureView {
+    Materials,
+    /// A heatmap of one pheromone channel. Walls ([PHEROMONE_MAX]) are magenta.
+    Pheromone(PheromoneChannel),
+    /// Black is 0, white is 255.
+    Height,
+    /// A heatmap of how worn down each tile is.
+    TimesChanged,
+    /// `kill_flag` is red, `kill_ants_only` is blue and both is magenta.
+    /// Flags set with [GridWorld::for_tile_in_radius] are cleared at the end of the tick,
+    /// so those only flash up for a frame.
+    KillFlags,
+}
+
+impl TextureView {
+    pub const ALL: [TextureView; 7] = [
+        TextureView::Materials,
+        TextureView::Pheromone(PheromoneChannel::Home),
+        TextureView::Pheromone(PheromoneChannel::Food),
+        TextureView::Pheromone(PheromoneChannel::Alarm),
+        TextureView::Height,
+        TextureView::TimesChanged,
+        TextureView::KillFlags,
+    ];
+
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|v| *v == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+impl GridWorld {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![
+                Tile {
+                    height: 128,
+                    material: TileMaterial::Earth,
+                    pheromones: Pheromones::default(),
+                    kill_flag: false,
+                    kill_ants_only: false,
+                    times_changed: 0
+                };
+                width * height
+            ],
+            pheromone_config: PheromoneConfig::default(),
+            pheromone_baseline: vec![0.0; width * height],
+            tile_physics: false,
+            texture_scratch: Vec::new(),
+            texture: None,
+            dirty_chunks: vec![
+                true;
+                width.div_ceil(TEXTURE_CHUNK_SIZE)
+                    * height.div_ceil(TEXTURE_CHUNK_SIZE)
+            ],
+            changed_tiles: None,
+            tiles_to_update: VecDeque::new(),
+            pheromone_scratch: PheromoneScratch::default(),
+        }
+    }
+
+    pub fn update_tiles(&mut self, random: &mut Random, materials: &MaterialRegistry) {
+        while let Some(i) = self.tiles_to_update.pop_back() {
+            let tile = &mut self.tiles[i];
+            tile.kill_flag = false;
+            tile.kill_ants_only = false;
+        }
+
+        if self.tile_physics {
+            self.update_tile_physics(random, materials);
+        }
+    }
+
+    /// A falling sand style pass. Materials turn into what they settle into,
+    /// and tiles much higher than their lowest neighbour slide down into it.
+    ///
+    /// What slides can be moved again later in the same pass, so going through the tiles
+    /// in the same order every tick would push everything one way.
+    /// So half the time they're gone through backwards, and which neighbour wins a tie changes.
+    fn update_tile_physics(&mut self, random: &mut Random, materials: &MaterialRegistry) {
+        let backwards = random.f32() < 0.5;
+        let first_neighbour = random.range_u32(0..4) as usize;
+        let tile_count = self.tiles.len();
+        for step in 0..tile_count {
+            let i = if backwards {
+                tile_count - 1 - step
+            } else {
+                step
+            };
+            let behavior = materials.get(self.tiles[i].material);
+
+            if let Some((material, chance)) = behavior.settles_into {
+                if random.f32() < chance {
+                    self.tiles[i].material = material;
+                    self.mark_dirty(i);
+                }
+            }
+
+            let Some(slump) = behavior.slump else {
+                continue;
+            };
+
+            let x = i / self.height;
+            let y = i % self.height;
+            // Going round, so starting anywhere favours no direction.
+            let mut neighbours = [
+                (x.wrapping_sub(1), y),
+                (x, y.wrapping_sub(1)),
+                (x + 1, y),
+                (x, y + 1),
+            ];
+            neighbours.rotate_left(first_neighbour);
+            let mut lowest: Option<(usize, u8)> = None;
+            for (nx, ny) in neighbours {
+                if nx >= self.width || ny >= self.height {
+                    continue;
+                }
+                let j = nx * self.height + ny;
+                let is_lower = match lowest {
+                    Some((_, height)) => self.tiles[j].height < height,
+                    None => true,
+                };
+                if is_lower {
+                    lowest = Some((j, self.tiles[j].height));
+                }
+            }
+
+            let Some((j, lowest_height)) = lowest else {
+                continue;
+            };
+            let difference = self.tiles[i].height.saturating_sub(lowest_height);
+            if difference <= slump {
+                continue;
+            }
+
+            let amount = ((difference - slump) / 2).clamp(1, SLUMP_PER_TICK);
+            self.tiles[i].height -= amount;
+            self.tiles[j].height += amount;
+            if behavior.spreads {
+                self.tiles[j].material = self.tiles[i].material;
+            }
+            self.mark_dirty(i);
+            self.mark_dirty(j);
+        }
+    }
+
+    /// Sets up the home gradient that leads ants down to the closest of `targets`.
+    pub fn reset_pheromones(&mut self, random: &mut Random, targets: &[DefendTarget]) {
+        for (i, t) in self.tiles.iter_mut().enumerate() {
+            let x = i / self.height;
+            let y = i % self.height;
+
+            let base_level = 1000.0;
+            let height_pheremone_offset = (y as f32 / self.height as f32) * 200.0;
+            t.pheromones = Pheromones::default();
+            t.pheromones.home =
+                height_pheremone_offset.trunc() + random.range_u32(0..20) as f32 + base_level;
+
+            let distance_to_target =
+                defend::distance_to_closest(targets, Vec2::new(x as f32, y as f32)).unwrap_or(0.0);
+
+            t.pheromones.home += distance_to_target.trunc() * 2.0;
+
+            if y > self.height.saturating_sub(3) {
+                t.pheromones.home = PHEROMONE_MAX;
+            }
+            t.times_changed = 0;
+        }
+        self.set_pheromone_baseline();
+    }
+
+    pub fn neutral_pheremones(&mut self) {
+        for (_, t) in self.tiles.iter_mut().enumerate() {
+            t.pheromones = Pheromones::default();
+            t.times_changed = 0;
+        }
+        self.set_pheromone_baseline();
+    }
+
+    pub fn reverse_deterent_pheromones(&mut self) {
+        for (i, t) in self.tiles.iter_mut().enumerate() {
+            let y = i % self.height;
+
+            if y < self.height.saturating_sub(60) {
+                t.pheromones.home = PHEROMONE_MAX;
+            }
+            t.times_changed = 0;
+        }
+        self.set_pheromone_baseline();
+    }
+
+    fn set_pheromone_baseline(&mut self) {
+        self.pheromone_baseline.clear();
+        self.pheromone_baseline
+            .extend(self.tiles.iter().map(|t| t.pheromones.home));
+    }
+
+    /// Spreads pheromone trails to neighbouring tiles and fades them towards the baseline.
+    pub fn update_pheromones(&mut self, materials: &MaterialRegistry) {
+        for t in self.tiles.iter_mut() {
+            if let Some((channel, amount)) = materials.get(t.material).emits {
+                *t.pheromones.get_mut(channel) += amount;
+            }
+        }
+
+        for channel in PheromoneChannel::ALL {
+            self.diffuse_pheromone(channel, self.pheromone_config.rates(channel));
+        }
+    }
+
+    fn diffuse_pheromone(&mut self, channel: PheromoneChannel, rates: PheromoneRates) {
+        const LANES: usize = 8;
+        type Lanes = std::simd::Simd<f32, LANES>;
+
+        let PheromoneRates {
+            diffusion,
+            evaporation,
+        } = rates;
+        if diffusion == 0.0 && evaporation == 0.0 {
+            return;
+        }
+
+        let baseline = |i: usize| match channel {
+            PheromoneChannel::Home => self.pheromone_baseline[i],
+            _ => 0.0,
+        };
+
+        // The trail on each tile is padded by a ring of tiles that copy their neighbour,
+        // so nothing spreads out of the edges of the world.
+        let padded_height = self.height + 2;
+        let padded_width = self.width + 2;
+        let trail = &mut self.pheromone_scratch.trail;
+        trail.clear();
+        trail.resize(padded_width * padded_height, 0.0);
+        for x in 0..padded_width {
+            let source_x = x.saturating_sub(1).min(self.width.saturating_sub(1));
+            for y in 0..padded_height {
+                let source_y = y.saturating_sub(1).min(self.height.saturating_sub(1));
+                let i = source_x * self.height + source_y;
+                trail[x * padded_height + y] = self.tiles[i].pheromones.get(channel) - baseline(i);
+            }
+        }
+
+        let new_trail = &mut self.pheromone_scratch.new_trail;
+        new_trail.clear();
+        new_trail.resize(self.width * self.height, 0.0);
+
+        let keep = 1.0 - evaporation;
+        for x in 0..self.width {
+            let column = (x + 1) * padded_height + 1;
+            let out_column = x * self.height;
+
+            let mut y = 0;
+            while y + LANES <= self.height {
+                let i = column + y;
+                let center = Lanes::from_slice(&trail[i..i + LANES]);
+                let neighbours = Lanes::from_slice(&trail[i - 1..i - 1 + LANES])
+                    + Lanes::from_slice(&trail[i + 1..i + 1 + LANES])
+                    + Lanes::from_slice(&trail[i - padded_height..i - padded_height + LANES])
+                    + Lanes::from_slice(&trail[i + padded_height..i + padded_height + LANES]);
+
+                let spread =
+                    center + (neighbours * Lanes::splat(0.25) - center) * Lanes::splat(diffusion);
+                (spread * Lanes::splat(keep))
+                    .copy_to_slice(&mut new_trail[out_column + y..out_column + y + LANES]);
+                y += LANES;
+            }
+
+            // Whatever doesn't fit in a full set of lanes.
+            while y < self.height {
+                let i = column + y;
+                let center = trail[i];
+                let neighbours = trail[i - 1]
+                    + trail[i + 1]
+                    + trail[i - padded_height]
+                    + trail[i + padded_height];
+                let spread = center + (neighbours * 0.25 - center) * diffusion;
+                new_trail[out_column + y] = spread * keep;
+                y += 1;
+            }
+        }
+
+        for (i, (t, trail)) in self.tiles.iter_mut().zip(new_trail.iter()).enumerate() {
+            *t.pheromones.get_mut(channel) = baseline(i) + trail;
+        }
+    }
+
+    pub fn initial_terrain_setup(
+        &mut self,
+        random: &mut Random,
+        config: &TerrainConfig,
+        targets: &[DefendTarget],
+    ) {
+        let noise2d: clatter::Simplex2d = clatter::Simplex2d::new();
+
+        // The noise itself is fixed, so pick where in it to sample from.
+        let height_offset = Vec2::new(
+            random.range_f32(0.0..10000.0),
+            random.range_f32(0.0..10000.0),
+        );
+        let terrain_type_offset = Vec2::new(
+            random.range_f32(0.0..10000.0),
+            random.range_f32(0.0..10000.0),
+        );
+        // Only picked when there are rocks, so biomes without them are laid out like they always were.
+        let rock_offset = config.rock_threshold.map(|_| {
+            Vec2::new(
+                random.range_f32(0.0..10000.0),
+                random.range_f32(0.0..10000.0),
+            )
+        });
+
+        let sample = |octaves: usize, x: usize, y: usize, offset: Vec2| {
+            (sample_with_octaves::<MAX_OCTAVES>(
+                &noise2d,
+                0.5,
+                octaves,
+                x as f32 / config.noise_scale + offset.x,
+                y as f32 / config.noise_scale + offset.y,
+            ) + 1.0)
+                / 2.0
+        };
+
+        for (i, t) in self.tiles.iter_mut().enumerate() {
+            t.material = TileMaterial::Earth;
+            let x = i / self.height;
+            let y = i % self.height;
+
+            let sample2d = sample(config.height_octaves, x, y, height_offset);
+            let terrain_type = sample(config.material_octaves, x, y, terrain_type_offset);
+
+            if terrain_type > config.grass_threshold {
+                t.material = TileMaterial::Grass;
+            }
+
+            let offset = config.height_range;
+            let height = (sample2d * offset) + (255.0 - offset);
+            t.height = height as u8;
+
+            if let Some(water_level) = config.water_level {
+                if sample2d < water_level {
+                    // Water is flat.
+                    t.material = TileMaterial::Water;
+                    t.height = ((water_level * offset) + (255.0 - offset)) as u8;
+                }
+            }
+
+            if let (Some(rock_threshold), Some(rock_offset)) = (config.rock_threshold, rock_offset)
+            {
+                if sample(config.material_octaves, x, y, rock_offset) > rock_threshold {
+                    t.height = 255;
+                }
+            }
+
+            t.times_changed = 0;
+        }
+        self.mark_all_dirty();
+
+        self.reset_pheromones(random, targets);
+    }
+
+    pub fn get_tile(&self, x: f32, y: f32) -> Option<&Tile> {
+        self.view().get_tile(x, y)
+    }
+
+    /// Marks the tile to be redrawn, so reads should use [GridWorld::get_tile].
+    pub fn get_tile_mut(&mut self, x: f32, y: f32) -> Option<&mut Tile> {
+        let i = tile_index(self.width, self.height, x, y)?;
+        self.mark_dirty(i);
+        self.tiles.get_mut(i)
+    }
+
+    /// The tiles as they are now, to be read from several threads at once.
+    pub fn view(&self) -> TileView<'_> {
+        TileView {
+            width: self.width,
+            height: self.height,
+            tiles: &self.tiles,
+        }
+    }
+
+    /// Lets `f` change the tiles in a circle. It returns whether the tile should be
+    /// looked at by the tile physics. Tiles whose height or material changed are redrawn,
+    /// so reads should use [TileView::for_tile_in_radius] on [GridWorld::view].
+    pub fn for_tile_in_radius(
+        &mut self,
+        center: Vec2,
+        radius: f32,
+        mut f: impl FnMut(Vec2, f32, &mut Tile) -> bool,
+    ) {
+        let _timer = profile::timer(Section::ForTileInRadius);
+        let (min, max) = radius_bounds(self.width, self.height, center, radius);
+
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                let t = Vec2::new(x as f32, y as f32);
+                let i = x * self.height + y;
+                let distance = (t - center).length();
+                if distance < radius {
+                    let Some(tile) = self.tiles.get_mut(i) else {
+                        continue;
+                    };
+                    let drawn_before = (tile.height, tile.material);
+                    let update = f(t, distance, tile);
+                    if (tile.height, tile.material) != drawn_before {
+                        self.mark_dirty(i);
+                    }
+                    if update {
+                        self.tiles_to_update.push_back(i);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Marks a tile as needing to be redrawn in the [TextureView::Materials] texture,
+    /// and as changed for [GridWorld::take_changed_tiles].
+    fn mark_dirty(&mut self, i: usize) {
+        let x = i / self.height;
+        let y = i % self.height;
+        let chunks_high = self.height.div_ceil(TEXTURE_CHUNK_SIZE);
+        self.dirty_chunks[(x / TEXTURE_CHUNK_SIZE) * chunks_high + y / TEXTURE_CHUNK_SIZE] = true;
+
+        if let Some(changed) = &mut self.changed_tiles {
+            // Past this it's quicker to look at every tile.
+            if changed.len() < self.tiles.len() {
+                changed.push(i);
+            } else {
+                self.changed_tiles = None;
+            }
+        }
+    }
+
+    pub(crate) fn mark_all_dirty(&mut self) {
+        self.dirty_chunks.iter_mut().for_each(|d| *d = true);
+        self.changed_tiles = None;
+    }
+
+    /// The tiles whose height or material might have changed since this was last called,
+    /// `None` if it could be any of them. A tile can be in there more than once,
+    /// or without having changed at all.
+    pub fn take_changed_tiles(&mut self) -> Option<Vec<usize>> {
+        self.changed_tiles.replace(Vec::new())
+    }
+
+    /// Takes over the texture `other` was drawing to, so a replaced world draws to the same one.
+    /// If it's a different size [GridWorld::update_texture] replaces it with one that fits.
+    pub fn take_texture_from(&mut self, other: &mut GridWorld) {
+        self.texture = other.texture.take();
+        self.mark_all_dirty();
+    }
+
+    /// Draws the tiles to `entity`'s texture.
+    /// The texture and material are made the first time and reused after that,
+    /// unless the world is a different size to the texture. Then new ones replace them
+    /// on `entity`, which drops the last handles to the old ones so they're freed.
+    /// Only chunks of tiles that changed since the last call are uploaded,
+    /// except in the debug views, which change everywhere every tick.
+    pub fn update_texture(
+        &mut self,
+        world: &mut World,
+        resources: &Resources,
+        entity: Entity,
+        view: TextureView,
+        materials: &MaterialRegistry,
+    ) {
+        let graphics = &mut resources.get::<Renderer>().raw_graphics_context;
+        let texture_settings = TextureSettings {
+            minification_filter: FilterMode::Nearest,
+            magnification_filter: FilterMode::Nearest,
+            generate_mipmaps: false,
+            ..Default::default()
+        };
+
+        if self
+            .texture
+            .as_ref()
+            .is_some_and(|t| (t.width, t.height) != (self.width, self.height))
+        {
+            self.texture = None;
+        }
+
+        let Some(grid_texture) = &mut self.texture else {
+            let (min, max) = self.heatmap_range(view);
+            self.texture_scratch.clear();
+            self.texture_scratch.reserve(self.width * self.height);
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let t = &self.tiles[x * self.height + (self.height - 1 - y)];
+                    let c = tile_color(t, view, materials, min, max).to_srgb();
+                    self.texture_scratch.push([c[0], c[1], c[2], c[3]]);
+                }
+            }
+
+            let new_texture = graphics.new_texture_with_data(
+                self.width as _,
+                self.height as _,
+                1,
+                &self.texture_scratch,
+                texture_settings,
+            );
+
+            let texture = resources
+                .get::<AssetStore<Texture>>()
+                .add(Texture(new_texture));
+
+            let material = resources.get::<AssetStore<Material>>().add(Material {
+                base_color_texture: Some(texture.clone()),
+                shader: Shader::UNLIT,
+                ..Default::default()
+            });
+
+            let _ = world.insert_one(entity, material);
+            self.texture = Some(GridTexture {
+                texture,
+                view,
+                width: self.width,
+                height: self.height,
+            });
+            self.dirty_chunks.iter_mut().for_each(|d| *d = false);
+            return;
+        };
+
+        if view != TextureView::Materials || grid_texture.view != view {
+            self.dirty_chunks.iter_mut().for_each(|d| *d = true);
+        }
+        grid_texture.view = view;
+
+        let textures = resources.get::<AssetStore<Texture>>();
+        let texture = &textures.get(&grid_texture.texture).0;
+
+        self.draw_dirty_chunks(view, materials, |min_x, min_y, width, height, pixels| {
+            graphics.update_texture(
+                texture,
+                min_x as _,
+                min_y as _,
+                0,
+                width as _,
+                height as _,
+                1,
+                pixels,
+                texture_settings.clone(),
+            );
+        });
+    }
+
+    /// Works out the colours of the chunks of tiles that changed since they were last drawn.
+    /// Each is handed to `upload` with its corner, counted from the top left of the texture,
+    /// and its size.
+    pub fn draw_dirty_chunks(
+        &mut self,
+        view: TextureView,
+        materials: &MaterialRegistry,
+        mut upload: impl FnMut(usize, usize, usize, usize, &[[f32; 4]]),
+    ) {
+        let _timer = profile::timer(Section::UpdateTexture);
+        let (min, max) = self.heatmap_range(view);
+
+        let chunks_high = self.height.div_ceil(TEXTURE_CHUNK_SIZE);
+        for (chunk, dirty) in self.dirty_chunks.iter_mut().enumerate() {
+            if !*dirty {
+                continue;
+            }
+            *dirty = false;
+
+            let min_x = (chunk / chunks_high) * TEXTURE_CHUNK_SIZE;
+            let min_y = (chunk % chunks_high) * TEXTURE_CHUNK_SIZE;
+            let max_x = (min_x + TEXTURE_CHUNK_SIZE).min(self.width);
+            let max_y = (min_y + TEXTURE_CHUNK_SIZE).min(self.height);
+
+            // The texture is upside down compared to the tiles.
+            self.texture_scratch.clear();
+            for y in (min_y..max_y).rev() {
+                for x in min_x..max_x {
+                    let t = &self.tiles[x * self.height + y];
+                    let c = tile_color(t, view, materials, min, max).to_srgb();
+                    self.texture_scratch.push([c[0], c[1], c[2], c[3]]);
+                }
+            }
+
+            upload(
+                min_x,
+                self.height - max_y,
+                max_x - min_x,
+                max_y - min_y,
+                &self.texture_scratch,
+            );
+        }
+    }
+
+    fn heatmap_range(&self, view: TextureView) -> (f32, f32) {
+        // Heatmaps are scaled to what's currently on the map so faint trails still show up.
+        match view {
+            TextureView::Pheromone(channel) => self
+                .tiles
+                .iter()
+                .map(|t| t.pheromones.get(channel))
+                .filter(|p| *p < PHEROMONE_MAX)
+                .fold((f32::MAX, f32::MIN), |(min, max), p| {
+                    (min.min(p), max.max(p))
+                }),
+            TextureView::TimesChanged => (
+                0.0,
+                self.tiles
+                    .iter()
+                    .map(|t| t.times_changed)
+                    .max()
+                    .unwrap_or(0) as f32,
+            ),
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+struct GridTexture {
+    texture: Handle<Texture>,
+    /// What was drawn to it last.
+    view: TextureView,
+    width: usize,
+    height: usize,
+}
+
+fn tile_color(
+    t: &Tile,
+    view: TextureView,
+    materials: &MaterialRegistry,
+    min: f32,
+    max: f32,
+) -> Color {
+    match view {
+        TextureView::Materials => Color::interpolate(
+            Color::from_srgb_hex(0x2A1605, 1.0),
+            materials.get(t.material).color(),
+            (t.height as f32 / 255.0).clamp(0.4, 1.0),
+        ),
+        TextureView::Pheromone(channel) => {
+            let p = t.pheromones.get(channel);
+            if p >= PHEROMONE_MAX {
+                Color::MAGENTA
+            } else {
+                heat_color(normalize(p, min, max))
+            }
+        }
+        TextureView::Height => {
+            let v = t.height as f32 / 255.0;
+            Color::new(v, v, v, 1.0)
+        }
+        TextureView::TimesChanged => heat_color(normalize(t.times_changed as f32, min, max)),
+        TextureView::KillFlags => match (t.kill_flag, t.kill_ants_only) {
+            (true, true) => Color::MAGENTA,
+            (true, false) => Color::RED,
+            (false, true) => Color::BLUE,
+            (false, false) => Color::BLACK,
+        },
+    }
+}
+
+fn normalize(v: f32, min: f32, max: f32) -> f32 {
+    if max > min {
+        ((v - min) / (max - min)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Black through red to yellow.
+fn heat_color(t: f32) -> Color {
+    if t < 0.5 {
+        Color::interpolate(Color::BLACK, Color::RED, t * 2.0)
+    } else {
+        Color::interpolate(Color::RED, Color::YELLOW, (t - 0.5) * 2.0)
+    }
+}
+
+#[derive(Default)]
+struct PheromoneScratch {
+    trail: Vec<f32>,
+    new_trail: Vec<f32>,
+}
+
+/// Layers `octaves` samples of noise, each at twice the frequency of the last.
+/// Lanes past `octaves` are sampled but don't count.
+fn sample_with_octaves<const LANES: usize>(
+    noise: &clatter::Simplex2d,
+    persistence: f32,
+    octaves: usize,
+    x: f32,
+    y: f32,
+) -> f32
+where
+    std::simd::LaneCount<LANES>: std::simd::SupportedLaneCount,
+{
+    use std::simd::num::SimdFloat;
+
+    let mut frequency = 1.0;
+    let mut amplitude = 1.0;
+    let mut max_value = 0.0;
+
+    let mut amplitudes: [f32; LANES] = [0.0; LANES];
+    let mut frequencies: [f32; LANES] = [0.0; LANES];
+
+    for i in 0..octaves.clamp(1, LANES) {
+        amplitudes[i] = amplitude;
+        frequencies[i] = frequency;
+
+        max_value += amplitude;
+        amplitude *= persistence;
+        frequency *= 2.0;
+    }
+
+    let amplitudes = core::simd::Simd::<f32, LANES>::from_array(amplitudes);
+    let frequencies = core::simd::Simd::<f32, LANES>::from_array(frequencies);
+    let sample = noise.sample([
+        core::simd::Simd::<f32, LANES>::splat(x) * frequencies,
+        core::simd::Simd::<f32, LANES>::splat(y) * frequencies,
+    ]) * amplitudes;
+
+    sample.value.reduce_sum() / max_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many chunks [GridWorld::draw_dirty_chunks] would upload.
+    fn chunks_drawn(grid_world: &mut GridWorld) -> usize {
+        let mut drawn = 0;
+        grid_world.draw_dirty_chunks(
+            TextureView::Materials,
+            &MaterialRegistry::new(),
+            |_, _, _, _, _| drawn += 1,
+        );
+        drawn
+    }
+
+    #[test]
+    fn only_chunks_with_changed_tiles_are_drawn() {
+        let mut grid_world = GridWorld::new(64, 64);
+        assert_eq!(chunks_drawn(&mut grid_world), 16);
+        assert_eq!(chunks_drawn(&mut grid_world), 0);
+
+        // Scent isn't drawn in the materials view.
+        grid_world.for_tile_in_radius(Vec2::new(40.0, 40.0), 5.0, |_, _, t| {
+            t.pheromones.food += 1.0;
+            false
+        });
+        grid_world
+            .view()
+            .for_tile_in_radius(Vec2::new(8.0, 8.0), 5.0, |_, _, _| {});
+        assert_eq!(chunks_drawn(&mut grid_world), 0);
+
+        grid_world.for_tile_in_radius(Vec2::new(40.0, 40.0), 1.0, |_, _, t| {
+            t.material = TileMaterial::Nacho;
+            false
+        });
+        assert_eq!(chunks_drawn(&mut grid_world), 1);
+    }
+
+    #[test]
+    fn pheromones_spread_the_same_as_one_tile_at_a_time() {
+        // Neither side is a multiple of the lanes, so the leftover tiles are covered too.
+        let (width, height) = (37, 45);
+        let mut grid_world = GridWorld::new(width, height);
+        let mut random = Random::new_with_seed(9);
+        for (t, baseline) in grid_world
+            .tiles
+            .iter_mut()
+            .zip(grid_world.pheromone_baseline.iter_mut())
+        {
+            t.pheromones.home = random.range_f32(0.0..100.0);
+            t.pheromones.food = random.range_f32(0.0..100.0);
+            *baseline = random.range_f32(0.0..50.0);
+        }
+
+        let rates = PheromoneRates {
+            diffusion: 0.3,
+            evaporation: 0.05,
+        };
+        for channel in [PheromoneChannel::Home, PheromoneChannel::Food] {
+            let baseline = |i: usize| match channel {
+                PheromoneChannel::Home => grid_world.pheromone_baseline[i],
+                _ => 0.0,
+            };
+            // Off the edge of the world reads as the tile on the edge.
+            let trail = |x: isize, y: isize| {
+                let x = x.clamp(0, width as isize - 1) as usize;
+                let y = y.clamp(0, height as isize - 1) as usize;
+                let i = x * height + y;
+                grid_world.tiles[i].pheromones.get(channel) - baseline(i)
+            };
+
+            let mut expected = Vec::new();
+            for x in 0..width as isize {
+                for y in 0..height as isize {
+                    let center = trail(x, y);
+                    let neighbours =
+                        trail(x - 1, y) + trail(x + 1, y) + trail(x, y - 1) + trail(x, y + 1);
+                    let spread = center + (neighbours * 0.25 - center) * rates.diffusion;
+                    expected.push(baseline(expected.len()) + spread * (1.0 - rates.evaporation));
+                }
+            }
+
+            grid_world.diffuse_pheromone(channel, rates);
+            for (i, expected) in expected.into_iter().enumerate() {
+                let found = grid_world.tiles[i].pheromones.get(channel);
+                assert!(
+                    (found - expected).abs() < 1e-3,
+                    "tile {} is {} but should be {}",
+                    i,
+                    found,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn slumping_doesnt_drift_one_way() {
+        let size = 61;
+        let mut grid_world = GridWorld::new(size, size);
+        for t in grid_world.tiles.iter_mut() {
+            t.material = TileMaterial::Earth;
+            t.height = 100;
+        }
+        let middle = size / 2;
+        for x in middle - 2..=middle + 2 {
+            for y in middle - 2..=middle + 2 {
+                grid_world.tiles[x * size + y].height = 255;
+            }
+        }
+
+        // Earth that slides at the smallest difference, so the pile spreads a long way.
+        let materials = MaterialRegistry::parse("{ Earth: (slump: Some(1)) }").unwrap();
+        let mut random = Random::new_with_seed(16);
+        for _ in 0..400 {
+            grid_world.update_tile_physics(&mut random, &materials);
+        }
+
+        // Where the pile's weight is centered, which should still be the middle.
+        let mut total = 0.0;
+        let mut center = [0.0, 0.0];
+        for (i, t) in grid_world.tiles.iter().enumerate() {
+            let height = t.height as f32 - 100.0;
+            total += height;
+            center[0] += (i / size) as f32 * height;
+            center[1] += (i % size) as f32 * height;
+        }
+        assert_eq!(total, 25.0 * 155.0);
+        let center = center.map(|c| c / total);
+        assert!(
+            center.iter().all(|c| (c - middle as f32).abs() < 0.25),
+            "the pile drifted to {:?}",
+            center
+        );
+    }
+}
@@ -0,0 +1,602 @@
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::enemies::EnemyKindId;
+use crate::grid_world::TileMaterial;
+use crate::sandbox::Brush;
+use crate::simulation::{
+    random_in_range, Ant, CurrentItem, SimEvent, Simulation, SpitBall, TickInput, STRAW_CAPACITY,
+    TILES_IN_SPITBALL,
+};
+use crate::{debug, info};
+
+/// How far up the screen the hand can reach, outside of sandbox mode.
+const MAX_HAND_REACH: f32 = 130.0;
+
+/// What an item's hooks get to work with besides the [Simulation].
+pub struct ItemContext<'a> {
+    pub input: &'a TickInput,
+    pub ants_to_despawn: &'a mut Vec<usize>,
+    pub events: &'a mut Vec<SimEvent>,
+}
+
+/// The textures the hand is drawn with while an item is held.
+pub struct HandArt {
+    pub idle: &'static str,
+    /// Shown while the pointer is held down.
+    pub pressed: Option<&'static str>,
+    /// Shown for a moment after a [SimEvent::GunFired].
+    pub fired: Option<&'static str>,
+}
+
+/// Something the player can hold.
+///
+/// Items don't keep any state of their own. Their ammo is kept by the [Hotbar]
+/// and anything else they need is on the [Simulation], so it's saved with everything else.
+///
+/// Each tick the held item's [Item::tick] runs, then [Item::on_press], [Item::on_hold]
+/// and [Item::on_release] if the pointer did those things.
+pub trait Item: Sync {
+    /// `None` leaves the hand as it was.
+    fn hand_art(&self) -> Option<HandArt>;
+
+    /// Shows a reticule at the pointer.
+    fn aims(&self) -> bool {
+        false
+    }
+
+    /// Can still be held once its ammo has run out.
+    fn equip_without_ammo(&self) -> bool {
+        false
+    }
+
+    /// Can't be used further up the screen than the hand reaches.
+    fn limited_reach(&self) -> bool {
+        true
+    }
+
+    fn equip(&self, _simulation: &mut Simulation) {}
+    fn tick(&self, _simulation: &mut Simulation, _context: &mut ItemContext) {}
+    fn on_press(&self, _simulation: &mut Simulation, _context: &mut ItemContext) {}
+    fn on_hold(&self, _simulation: &mut Simulation, _context: &mut ItemContext) {}
+    fn on_release(&self, _simulation: &mut Simulation, _context: &mut ItemContext) {}
+}
+
+pub fn item(item: CurrentItem) -> &'static dyn Item {
+    match item {
+        CurrentItem::Finger => &Finger,
+        CurrentItem::Straw => &Straw,
+        CurrentItem::Nacho => &Nacho,
+        CurrentItem::Toothpaste => &Toothpaste,
+        CurrentItem::Gun => &Gun,
+        CurrentItem::Spider => &Spider,
+        CurrentItem::Inspector => &Inspector,
+        CurrentItem::Brush => &Brush,
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HotbarSlot {
+    pub item: CurrentItem,
+    /// Whether the player has found it.
+    pub owned: bool,
+    /// `None` never runs out.
+    pub ammo: Option<i32>,
+}
+
+/// The items the player has, in the order of the number keys that pick them.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Hotbar {
+    pub slots: Vec<HotbarSlot>,
+    pub selected: CurrentItem,
+}
+
+impl Hotbar {
+    pub fn new() -> Self {
+        let slot = |item, owned, ammo| HotbarSlot { item, owned, ammo };
+        Self {
+            slots: vec![
+                slot(CurrentItem::Finger, true, None),
+                slot(CurrentItem::Straw, true, None),
+                slot(CurrentItem::Toothpaste, false, Some(0)),
+                slot(CurrentItem::Gun, false, Some(0)),
+                slot(CurrentItem::Nacho, true, Some(0)),
+                slot(CurrentItem::Spider, true, Some(0)),
+                // A developer tool, see [crate::simulation::Command::GrantItem].
+                slot(CurrentItem::Inspector, false, None),
+                // Handed over in sandbox mode.
+                slot(CurrentItem::Brush, false, None),
+            ],
+            selected: CurrentItem::Finger,
+        }
+    }
+
+    pub fn slot(&self, item: CurrentItem) -> &HotbarSlot {
+        self.slots.iter().find(|slot| slot.item == item).unwrap()
+    }
+
+    pub fn slot_mut(&mut self, item: CurrentItem) -> &mut HotbarSlot {
+        self.slots
+            .iter_mut()
+            .find(|slot| slot.item == item)
+            .unwrap()
+    }
+
+    /// How much ammo `item` has. Items that don't use ammo have [i32::MAX].
+    pub fn ammo(&self, item: CurrentItem) -> i32 {
+        self.slot(item).ammo.unwrap_or(i32::MAX)
+    }
+
+    /// Uses up one of `item`'s ammo. Returns false if there wasn't any.
+    pub fn take_ammo(&mut self, item: CurrentItem) -> bool {
+        match &mut self.slot_mut(item).ammo {
+            Some(ammo) if *ammo > 0 => {
+                *ammo -= 1;
+                true
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    pub fn add_ammo(&mut self, item: CurrentItem, amount: i32) {
+        if let Some(ammo) = &mut self.slot_mut(item).ammo {
+            *ammo = ammo.saturating_add(amount);
+        }
+    }
+
+    pub fn give(&mut self, item: CurrentItem) {
+        self.slot_mut(item).owned = true;
+    }
+
+    pub fn can_equip(&self, item: CurrentItem) -> bool {
+        let slot = self.slot(item);
+        slot.owned && (slot.ammo != Some(0) || self::item(item).equip_without_ammo())
+    }
+
+    /// The next item along from the selected one that can be equipped,
+    /// going backwards if `step` is negative and wrapping around the ends.
+    pub fn cycle(&self, step: isize) -> CurrentItem {
+        let len = self.slots.len() as isize;
+        let start = self
+            .slots
+            .iter()
+            .position(|slot| slot.item == self.selected)
+            .unwrap_or(0) as isize;
+
+        (1..len)
+            .map(|i| &self.slots[(start + step.signum() * i).rem_euclid(len) as usize])
+            .find(|slot| self.can_equip(slot.item))
+            .map_or(self.selected, |slot| slot.item)
+    }
+
+    /// Everything, with ammo that won't run out.
+    pub fn fill(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.owned = true;
+            if slot.ammo.is_some() {
+                slot.ammo = Some(i32::MAX);
+            }
+        }
+    }
+}
+
+pub struct Finger;
+
+impl Item for Finger {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/curledfinger.png",
+            pressed: Some("assets/pointedfinger.png"),
+            fired: None,
+        })
+    }
+
+    fn on_hold(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        let slide_scale = (simulation.pointer_position - simulation.pointer_last_position).length();
+        let pointer_down = context.input.pointer_down;
+
+        simulation.grid_world.for_tile_in_radius(
+            simulation.pointer_position.xy(),
+            3.0,
+            |_, _, t| {
+                if slide_scale > 0.2 {
+                    let amount = 14.0 * (slide_scale / 3.0);
+                    t.height = t.height.saturating_sub(amount as _);
+                }
+
+                if t.height < 30 {
+                    t.material = TileMaterial::Earth;
+                }
+
+                if pointer_down {
+                    t.kill_flag = true;
+                }
+                true
+            },
+        );
+    }
+}
+
+pub struct Straw;
+
+impl Item for Straw {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/strawhand.png",
+            pressed: None,
+            fired: None,
+        })
+    }
+
+    fn aims(&self) -> bool {
+        true
+    }
+
+    fn equip(&self, simulation: &mut Simulation) {
+        simulation.straw_sucking = false;
+    }
+
+    fn tick(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        if !context.input.pointer_held {
+            simulation.straw_sucking = false;
+        }
+    }
+
+    fn on_release(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        if simulation.pointer_held_len >= 20 {
+            return;
+        }
+
+        debug!("CAPACITY: {:?}", simulation.straw_contents.len());
+        if !simulation.straw_sucking && !simulation.straw_contents.is_empty() {
+            // Shoot back material
+            let mut material = Vec::new();
+
+            for _ in 0..TILES_IN_SPITBALL {
+                if let Some(mat) = simulation.straw_contents.pop_back() {
+                    material.push(mat);
+                }
+            }
+
+            simulation.spitballs.push(SpitBall {
+                position: simulation.pointer_position.xy().extend(-8.0),
+                target: context.input.pointer.xy(),
+                material,
+            });
+
+            context.events.push(SimEvent::Spit);
+        }
+    }
+
+    fn on_hold(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        if simulation.pointer_held_len <= 20 {
+            simulation.straw_sucking = false;
+            return;
+        }
+        if simulation.straw_contents.len() >= STRAW_CAPACITY && !simulation.straw_sucking {
+            return;
+        }
+
+        let origin = simulation.pointer_position;
+        let radius = 3.0;
+        let height_increments = 30;
+        let events = &mut *context.events;
+
+        if simulation.straw_contents.len() < STRAW_CAPACITY {
+            let suck_radius = 10.0;
+            let ants = &mut simulation.ants;
+            let ants_to_despawn = &mut *context.ants_to_despawn;
+            let wave_stats = &mut simulation.wave_stats;
+            let player_health = &mut simulation.player_health;
+            let straw_contents = &mut simulation.straw_contents;
+            simulation
+                .ant_hash
+                .for_each_in_radius(origin.xy(), suck_radius, |i, _| {
+                    let ant = &mut ants[i];
+                    let dir = ant.position - origin;
+                    let distance = dir.length();
+                    let normalized_dir = dir / distance;
+
+                    if distance < 2.0 {
+                        ants_to_despawn.push(i);
+                        wave_stats.ants_killed += 1;
+
+                        *player_health -= 1;
+                        events.push(SimEvent::AntSucked);
+
+                        for _ in 0..10 {
+                            straw_contents.push_back(TileMaterial::AntBody);
+                        }
+                    }
+
+                    if distance < suck_radius {
+                        ant.position -= normalized_dir * 0.9 * (1.0 - distance / suck_radius);
+                    }
+                });
+        }
+        // TODO: Check for ants
+        simulation.straw_sucking = true;
+        let straw_contents = &mut simulation.straw_contents;
+        let player_health = &mut simulation.player_health;
+        let materials = &simulation.materials;
+        let random = &mut simulation.random;
+        simulation
+            .grid_world
+            .for_tile_in_radius(origin.xy(), radius, |_, _, t| {
+                // Heal from chip crumbs and the like
+                let heal = materials.get(t.material).heal_when_sucked;
+                if heal != 0 {
+                    events.push(SimEvent::NachoSucked);
+                    if *player_health < 50 {
+                        *player_health += heal;
+                    }
+                    t.material = TileMaterial::Earth;
+                }
+
+                if straw_contents.len() < STRAW_CAPACITY && random.f32() > 0.96 {
+                    if t.height > height_increments as u8 {
+                        t.height = t.height.saturating_sub(height_increments * 1.4 as u8);
+                        straw_contents.push_back(t.material);
+                    }
+                }
+                false
+            });
+    }
+}
+
+pub struct Toothpaste;
+
+impl Toothpaste {
+    const MAX_SQUEEZE: f32 = 1.0;
+}
+
+impl Item for Toothpaste {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/toothpastehand.png",
+            pressed: None,
+            fired: None,
+        })
+    }
+
+    fn equip_without_ammo(&self) -> bool {
+        true
+    }
+
+    fn tick(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        if !context.input.pointer_held {
+            simulation.pointer_max_speed = None;
+        }
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, _context: &mut ItemContext) {
+        simulation.toothpaste_squeeze = Self::MAX_SQUEEZE;
+    }
+
+    fn on_hold(&self, simulation: &mut Simulation, _context: &mut ItemContext) {
+        let radius = 2.0 * (simulation.toothpaste_squeeze / Self::MAX_SQUEEZE);
+        let hotbar = &mut simulation.hotbar;
+        let random = &mut simulation.random;
+        simulation.grid_world.for_tile_in_radius(
+            simulation.pointer_position.xy(),
+            radius,
+            |_, r, t| {
+                match t.material {
+                    TileMaterial::ToothPaste1
+                    | TileMaterial::ToothPaste2
+                    | TileMaterial::ToothPaste3 => {
+                        return false;
+                    }
+                    _ => {}
+                }
+
+                if !hotbar.take_ammo(CurrentItem::Toothpaste) {
+                    return false;
+                }
+
+                t.height = 255;
+                t.height = (((1.0 - (r / radius)) * 200.0) as u8).max(t.height);
+
+                match random.range_u32(0..3) {
+                    0 => {
+                        t.material = TileMaterial::ToothPaste1;
+                    }
+                    1 => {
+                        t.material = TileMaterial::ToothPaste2;
+                    }
+                    2 => {
+                        t.material = TileMaterial::ToothPaste3;
+                    }
+                    _ => t.material = TileMaterial::ToothPaste1,
+                }
+                true
+            },
+        );
+
+        //  toothpaste_squeeze -= 0.02;
+        simulation.toothpaste_squeeze = simulation.toothpaste_squeeze.max(0.0);
+        simulation.pointer_max_speed = Some(10.0);
+    }
+}
+
+pub struct Gun;
+
+impl Item for Gun {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/pointedgun.png",
+            pressed: None,
+            fired: Some("assets/gunfire.png"),
+        })
+    }
+
+    fn aims(&self) -> bool {
+        true
+    }
+
+    fn equip_without_ammo(&self) -> bool {
+        true
+    }
+
+    fn tick(&self, simulation: &mut Simulation, _context: &mut ItemContext) {
+        simulation.pointer_max_speed = None;
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        if !simulation.hotbar.take_ammo(CurrentItem::Gun) {
+            context.events.push(SimEvent::OutOfBullets);
+            return;
+        }
+
+        let blast_radius = 6.0;
+        let random = &mut simulation.random;
+        simulation.grid_world.for_tile_in_radius(
+            context.input.pointer.xy(),
+            blast_radius,
+            |_, r, t| {
+                let how_close_to_center = 1.0 - (r / blast_radius);
+                let scale = random.f32() * how_close_to_center;
+
+                if scale > 0.1 {
+                    t.height = t.height.saturating_sub((150.0 * scale) as _);
+                    t.material = TileMaterial::Earth;
+                }
+
+                t.kill_flag = true;
+                true
+            },
+        );
+
+        context.events.push(SimEvent::GunFired);
+
+        let shot_at = simulation.pointer_position.xy();
+        if let Some(target) = simulation
+            .defend_targets
+            .iter()
+            .find(|target| target.is_hit_by_shot(shot_at))
+        {
+            debug!("{:?} SHOT AT: {:?}", target.kind, shot_at);
+            simulation.player_health -= target.kind.shot_damage();
+            context.events.push(SimEvent::FootShot);
+        }
+    }
+}
+
+pub struct Nacho;
+
+impl Item for Nacho {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/nachohand.png",
+            pressed: None,
+            fired: None,
+        })
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        let pointer = context.input.pointer.xy();
+        let radius = 2.0;
+
+        // The food scent that draws ants in comes from the nacho tiles themselves,
+        // see `emits` in materials.ron.
+        let hotbar = &mut simulation.hotbar;
+        let random = &mut simulation.random;
+        simulation
+            .grid_world
+            .for_tile_in_radius(pointer, radius, |_, _, t| {
+                if hotbar.ammo(CurrentItem::Nacho) > 0
+                    && t.material != TileMaterial::Nacho
+                    && random.f32() > 0.90
+                {
+                    t.material = TileMaterial::Nacho;
+                    hotbar.take_ammo(CurrentItem::Nacho);
+                }
+
+                false
+            });
+    }
+}
+
+/// Spiders are dropped where the hand is and hunt ants for the player.
+pub struct Spider;
+
+impl Item for Spider {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/spiderhand.png",
+            pressed: None,
+            fired: None,
+        })
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, _context: &mut ItemContext) {
+        if !simulation.hotbar.take_ammo(CurrentItem::Spider) {
+            return;
+        }
+
+        let spider = simulation.enemy_kinds.get(EnemyKindId::SPIDER);
+        simulation.ants.push(Ant {
+            position: simulation.pointer_position,
+            facing: std::f32::consts::PI / 2.0,
+            dir: -Vec2::Y,
+            speed: random_in_range(&mut simulation.random, spider.speed.0, spider.speed.1),
+            return_mode: false,
+            health: spider.health,
+            carrying: None,
+            kind: EnemyKindId::SPIDER,
+            home: simulation.pointer_position.xy(),
+        });
+    }
+}
+
+/// Prints what's in the tiles under the pointer.
+pub struct Inspector;
+
+impl Item for Inspector {
+    fn hand_art(&self) -> Option<HandArt> {
+        None
+    }
+
+    fn limited_reach(&self) -> bool {
+        false
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        let radius = 1.0;
+
+        simulation.grid_world.view().for_tile_in_radius(
+            context.input.pointer.xy(),
+            radius,
+            |_, _, t| {
+                info!("P: {:?}", t.pheromones);
+                info!("H: {:?}", t.height);
+            },
+        );
+    }
+}
+
+/// Runs the held item's hooks for this tick.
+pub(crate) fn update_item(simulation: &mut Simulation, context: &mut ItemContext) {
+    let item = item(simulation.hotbar.selected);
+
+    if item.limited_reach() && !simulation.sandbox_mode {
+        simulation.pointer_position.y = simulation.pointer_position.y.min(MAX_HAND_REACH);
+    }
+
+    item.tick(simulation, context);
+    if context.input.pointer_down {
+        item.on_press(simulation, context);
+    }
+    if context.input.pointer_held {
+        item.on_hold(simulation, context);
+    }
+    if context.input.pointer_released {
+        item.on_release(simulation, context);
+    }
+
+    // Put it away once it's used up.
+    if !simulation.hotbar.can_equip(simulation.hotbar.selected) {
+        simulation.hotbar.selected = CurrentItem::Finger;
+    }
+}
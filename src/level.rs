@@ -0,0 +1,177 @@
+//! Hand made terrain, made in the editor (`--edit <file>`) and played with `--level <file>`
+//! or picked by a [Wave](crate::waves::Wave)'s `level`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use koi3::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::defend::DefendTarget;
+use crate::file_format::{self, invalid_data, vec2s};
+use crate::grid_world::TileMaterial;
+use crate::simulation::Simulation;
+use crate::warn;
+use crate::waves::Wave;
+
+/// Levels from before they were RON started with this.
+const OLD_MAGIC: &[u8; 4] = b"LD5L";
+/// Bumped whenever [Level] changes. Levels of other versions are refused.
+const VERSION: u32 = 1;
+
+/// The world size used when nothing else picks one.
+pub const DEFAULT_WORLD_SIZE: Vec2 = Vec2::new(90.0, 200.0);
+/// Anything smaller leaves no room between where ants spawn and the defend targets.
+pub const MIN_WORLD_SIZE: Vec2 = Vec2::new(32.0, 64.0);
+/// Far bigger than the game can keep up with, but small enough to allocate.
+pub const MAX_WORLD_SIZE: Vec2 = Vec2::new(4096.0, 4096.0);
+
+/// Errors if a world or level would be smaller than [MIN_WORLD_SIZE] or bigger than
+/// [MAX_WORLD_SIZE].
+pub fn check_world_size(width: usize, height: usize) -> Result<(), String> {
+    if (width as f32) < MIN_WORLD_SIZE.x || (height as f32) < MIN_WORLD_SIZE.y {
+        return Err(format!(
+            "{}x{} is smaller than the smallest world, {}x{}",
+            width, height, MIN_WORLD_SIZE.x, MIN_WORLD_SIZE.y
+        ));
+    }
+    if (width as f32) > MAX_WORLD_SIZE.x || (height as f32) > MAX_WORLD_SIZE.y {
+        return Err(format!(
+            "{}x{} is bigger than the biggest world, {}x{}",
+            width, height, MAX_WORLD_SIZE.x, MAX_WORLD_SIZE.y
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Level {
+    pub width: usize,
+    pub height: usize,
+    /// Indexed like [GridWorld::tiles](crate::grid_world::GridWorld::tiles).
+    pub heights: Vec<u8>,
+    pub materials: Vec<TileMaterial>,
+    /// Where [SpawnPattern::LevelSpawns](crate::waves::SpawnPattern::LevelSpawns) waves come from.
+    #[serde(with = "vec2s")]
+    pub spawn_points: Vec<Vec2>,
+    pub defend_targets: Vec<DefendTarget>,
+}
+
+impl Level {
+    /// The terrain, spawn points and defend targets `simulation` has right now.
+    pub fn capture(simulation: &Simulation) -> Self {
+        let grid_world = &simulation.grid_world;
+        Self {
+            width: grid_world.width,
+            height: grid_world.height,
+            heights: grid_world.tiles.iter().map(|t| t.height).collect(),
+            materials: grid_world.tiles.iter().map(|t| t.material).collect(),
+            spawn_points: simulation.spawn_points.clone(),
+            defend_targets: simulation.defend_targets.clone(),
+        }
+    }
+
+    pub fn world_size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    /// Lays the level over `simulation`'s terrain and resets the pheromones,
+    /// like generating a biome does.
+    /// Errors if the level was made for a different world size.
+    pub fn apply(&self, simulation: &mut Simulation) -> Result<(), String> {
+        let grid_world = &mut simulation.grid_world;
+        if (grid_world.width, grid_world.height) != (self.width, self.height) {
+            return Err(format!(
+                "the level is {}x{} but the world is {}x{}",
+                self.width, self.height, grid_world.width, grid_world.height
+            ));
+        }
+        let size = self.world_size();
+        let outside = |p: Vec2| !(p.x >= 0.0 && p.y >= 0.0 && p.x < size.x && p.y < size.y);
+        if let Some(point) = self.spawn_points.iter().find(|p| outside(**p)) {
+            return Err(format!("spawn point {:?} is outside the level", point));
+        }
+        if let Some(target) = self.defend_targets.iter().find(|t| outside(t.position)) {
+            return Err(format!("defend target {:?} is outside the level", target));
+        }
+
+        for ((t, height), material) in grid_world
+            .tiles
+            .iter_mut()
+            .zip(self.heights.iter())
+            .zip(self.materials.iter())
+        {
+            t.height = *height;
+            t.material = *material;
+            t.kill_flag = false;
+            t.kill_ants_only = false;
+        }
+        grid_world.mark_all_dirty();
+
+        simulation.spawn_points = self.spawn_points.clone();
+        simulation.defend_targets = self.defend_targets.clone();
+        simulation
+            .grid_world
+            .reset_pheromones(&mut simulation.random, &simulation.defend_targets);
+        Ok(())
+    }
+
+    /// Errors if the tiles don't fit the level's size, or that size can't be a world's.
+    pub fn check(&self) -> Result<(), String> {
+        check_world_size(self.width, self.height)?;
+        let tile_count = self.width * self.height;
+        if self.heights.len() != tile_count || self.materials.len() != tile_count {
+            return Err("the tiles in the level don't fit its size".into());
+        }
+        Ok(())
+    }
+
+    pub fn to_ron(&self) -> String {
+        file_format::to_ron(VERSION, self)
+    }
+
+    pub fn from_ron(source: &str) -> std::io::Result<Self> {
+        let level: Self = file_format::from_ron(source, VERSION, "level")?;
+        level.check().map_err(invalid_data)?;
+        Ok(level)
+    }
+
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_ron())
+    }
+
+    pub fn load_from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(OLD_MAGIC) {
+            return Err(invalid_data(
+                "level is from before levels were RON and can't be loaded",
+            ));
+        }
+        let source = std::str::from_utf8(&bytes).map_err(|_| invalid_data("not a level"))?;
+        Self::from_ron(source)
+    }
+}
+
+/// Loads every level the waves refer to, keyed by the path they're referred to by.
+/// Levels that can't be loaded are left out, and their waves keep the terrain they find.
+pub fn load_wave_levels(waves: &[Wave]) -> HashMap<String, Level> {
+    let mut levels = HashMap::new();
+    load_missing_levels(&mut levels, waves);
+    levels
+}
+
+/// Like [load_wave_levels] but only loads the levels that aren't in `levels` already.
+pub fn load_missing_levels(levels: &mut HashMap<String, Level>, waves: &[Wave]) {
+    for path in waves.iter().filter_map(|wave| wave.level.as_ref()) {
+        if levels.contains_key(path) {
+            continue;
+        }
+        match Level::load_from_file(path) {
+            Ok(level) => {
+                levels.insert(path.clone(), level);
+            }
+            Err(e) => warn!("Could not load level {:?}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+#![feature(portable_simd)]
+
+pub mod bindings;
+pub mod defend;
+pub mod enemies;
+mod file_format;
+pub mod flow_field;
+pub mod gamepad;
+pub mod grid_world;
+pub mod items;
+pub mod level;
+pub mod log;
+pub mod materials;
+pub mod profile;
+pub mod replay;
+pub mod sandbox;
+pub mod save;
+pub mod simulation;
+pub mod slider;
+pub mod spatial_hash;
+pub mod waves;
@@ -0,0 +1,87 @@
+//! A small leveled logger for diagnostics.
+//! Use the [error!](crate::error), [warn!](crate::warn), [info!](crate::info)
+//! and [debug!](crate::debug) macros.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    /// Per-event chatter, like waves spawning and ants getting past.
+    Debug,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            _ => return None,
+        })
+    }
+
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Messages less important than `level` aren't printed.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= self::level()
+}
+
+#[doc(hidden)]
+pub fn write(level: Level, message: std::fmt::Arguments) {
+    match level {
+        Level::Error | Level::Warn => eprintln!("[{:?}] {}", level, message),
+        Level::Info | Level::Debug => println!("[{:?}] {}", level, message),
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
@@ -1,13 +1,135 @@
-#![feature(portable_simd)]
+use std::collections::HashMap;
 
-use std::{
-    collections::{HashSet, VecDeque},
+use koi3::*;
+use koi_graphics_context::FilterMode;
+
+use ld52::bindings::{Action, Binding, Bindings};
+use ld52::defend::{DefendTarget, DefendTargetKind};
+use ld52::gamepad::Gamepads;
+use ld52::grid_world::{PheromoneConfig, TextureView};
+use ld52::items;
+use ld52::level::{self, Level, DEFAULT_WORLD_SIZE, MIN_WORLD_SIZE};
+use ld52::log;
+use ld52::sandbox::SandboxCommand;
+use ld52::{error, info, warn};
+use ld52::materials::MaterialRegistry;
+use ld52::replay::{Recording, RecordingWriter};
+use ld52::save;
+use ld52::simulation::{
+    Ant, AntUpdate, Command, CurrentItem, SimEvent, Simulation, SimulationConfig, SpitBall,
+    TickInput, PLAYER_HEALTH_MAX, STRAW_CAPACITY,
 };
+use ld52::slider::{Slider, SliderOption};
+use ld52::waves::{Wave, WaveFile};
 
-use koi3::*;
-use koi_graphics_context::{FilterMode, TextureSettings};
+/// How far the pointer moves each tick with a gamepad's stick pushed all the way.
+const GAMEPAD_POINTER_SPEED: f32 = 1.5;
 
 fn main() {
+    let args = Args::parse();
+    // Developers get the per-event chatter too.
+    log::set_level(args.log_level.unwrap_or(if args.dev {
+        log::Level::Debug
+    } else {
+        log::Level::Info
+    }));
+    let dev_mode = args.dev;
+
+    let recording = args.replay.as_ref().map(|path| {
+        Recording::load(path).unwrap_or_else(|e| {
+            exit_with_error(&format!("Could not load recording {:?}: {}", path, e))
+        })
+    });
+
+    let mut wave_file = WaveFile::new(args.waves.clone().unwrap_or("assets/waves.ron".into()));
+    let config = simulation_config(&args, &wave_file);
+
+    let bindings_path = "assets/bindings.ron";
+    let bindings = if std::path::Path::new(bindings_path).exists() {
+        Bindings::load(bindings_path).unwrap_or_else(|e| {
+            warn!("Could not load {:?}: {}", bindings_path, e);
+            Bindings::default()
+        })
+    } else {
+        Bindings::default()
+    };
+
+    if args.headless {
+        let Some(recording) = recording else {
+            exit_with_error("--headless needs a recording to play: --replay <file>");
+        };
+
+        if let Some(at_tick) = args.save_round_trip {
+            match save::check_round_trip(&recording, at_tick) {
+                Ok(true) => info!("SAVE ROUND TRIP: same state"),
+                Ok(false) => exit_with_error("SAVE ROUND TRIP: states differ"),
+                Err(e) => {
+                    exit_with_error(&format!("SAVE ROUND TRIP: could not load the save: {}", e))
+                }
+            }
+            return;
+        }
+
+        let simulation = recording.play_headless();
+        info!(
+            "PLAYED {} TICKS: player_health {}, current_wave {}, ants {}",
+            recording.ticks.len(),
+            simulation.player_health,
+            simulation.current_wave,
+            simulation.ants.len()
+        );
+        return;
+    }
+
+    let mut loaded_simulation = args.load.as_ref().map(|path| {
+        if recording.is_some() || args.record.is_some() {
+            exit_with_error("--load can't be used with --replay or --record");
+        }
+        let mut simulation = save::load_from_file(path)
+            .unwrap_or_else(|e| exit_with_error(&format!("Could not load save {:?}: {}", path, e)));
+        simulation.configure_loaded(&config);
+        simulation
+    });
+    let save_path = args.save.clone().unwrap_or("savegame.ld52".into());
+
+    let edit_path = args.edit.clone();
+    if edit_path.is_some() && (recording.is_some() || args.record.is_some() || args.load.is_some())
+    {
+        exit_with_error("--edit can't be used with --replay, --record or --load");
+    }
+    // Editing a file that isn't there yet starts a new level.
+    let edited_level = edit_path.as_ref().filter(|path| path.exists()).map(|path| {
+        Level::load_from_file(path)
+            .unwrap_or_else(|e| exit_with_error(&format!("Could not load level {:?}: {}", path, e)))
+    });
+
+    let (seed, world_size) = match (&recording, &loaded_simulation) {
+        (Some(recording), _) => (recording.seed, recording.world_size),
+        (None, Some(simulation)) => (simulation.seed, simulation.world_size),
+        (None, None) => (
+            args.seed.unwrap_or_else(fresh_seed),
+            // A level only fits the world size it was made for.
+            edited_level
+                .as_ref()
+                .or_else(|| args.level.as_ref().and_then(|path| config.levels.get(path)))
+                .map_or(args.world_size.unwrap_or(DEFAULT_WORLD_SIZE), Level::world_size),
+        ),
+    };
+    info!("SEED: {:?}", seed);
+
+    // A recording plays back with what it was recorded with, not what's in `assets` now.
+    let config = match &recording {
+        Some(recording) => recording.config.clone(),
+        None => config,
+    };
+
+    let mut recorder = args.record.as_ref().map(|path| {
+        RecordingWriter::create(path, seed, world_size, &config).unwrap_or_else(|e| {
+            exit_with_error(&format!("Could not create recording {:?}: {}", path, e))
+        })
+    });
+    let mut replay_ticks = recording.map(|recording| recording.ticks.into_iter());
+
     App::default()
         .with_resource(InitialSettings {
             color_space: koi_graphics_context::ColorSpace::SRGB,
@@ -16,10 +138,10 @@ fn main() {
             ..Default::default()
         })
         .setup_and_run(|world, resources| {
+            // The top ones are measured from the top of the world so taller worlds scroll further.
             let camera_min = 60.0;
-            let camera_max = 140.0;
-
-            let world_size = Vec2::new(90.0, 200.0);
+            let camera_max = (world_size.y - 60.0).max(camera_min);
+            let camera_top = (world_size.y - 40.0).max(camera_min);
 
             let camera = world.spawn((Transform::new().with_position(Vec3::new(
                 world_size.x / 2.0,
@@ -49,56 +171,57 @@ fn main() {
                 Mesh::VERTICAL_QUAD,
             ));
 
-            let mut grid_world = GridWorld::new(world_size.x as _, world_size.y as _);
-            grid_world.initial_terrain_setup();
-            grid_world.reverse_deterent_pheromones();
-
-            let ant_material = get_texture_material(
-                "assets/AntFrame1.png",
-                resources,
-                Shader::UNLIT,
-                Color::BLACK,
-            );
-
-            let spider_material = get_texture_material(
-                "assets/spider.png",
-                resources,
-                Shader::UNLIT,
-                Color::RED,
-            );
-
-            let a_material = ant_material.clone();
-            let spawn_ants = move |world: &mut World, ant_count: usize| {
-                let mut random = Random::new();
-
-                for i in 0..ant_count {
-                    world.spawn((
-                        Transform::new()
-                            .with_position(
-                                Vec2::new(
-                                    (i as f32 / ant_count as f32) * world_size.x,
-                                    world_size.y - 5.0,
-                                )
-                                .extend(-3.0),
-                            )
-                            .with_scale(Vec3::fill(2.0)),
-                        Ant {
-                            dir: -Vec2::Y,
-                            speed: random.range_f32(0.05..0.2),
-                            return_mode: false,
-                            health: 255,
-                            carrying: None,
-                            home: Vec2::ZERO,
-                            is_spider: false,
-                        },
-                        Mesh::VERTICAL_QUAD,
-                        //Material::UNLIT,
-                        a_material.clone(),
-                    ));
+            let mut simulation = loaded_simulation.take().unwrap_or_else(|| {
+                let mut simulation = Simulation::new(world_size, seed);
+                simulation.configure(config.clone());
+                simulation
+            });
+            if let Some(path) = &edit_path {
+                if let Some(level) = &edited_level {
+                    if let Err(e) = level.apply(&mut simulation) {
+                        warn!("Could not use level {:?}: {}", path, e);
+                    }
                 }
-            };
-            // Setup a few ants for the first screen.
-            spawn_ants(world, 10);
+                simulation.enter_editor();
+                info!("EDITING {:?}, F5 saves it and F9 goes back to the saved copy", path);
+            }
+            let mut wave_reload_timer = 0;
+
+            // Indexed by `EnemyKindId`.
+            let enemy_materials: Vec<Handle<Material>> = simulation
+                .enemy_kinds
+                .iter()
+                .map(|(_, kind)| {
+                    get_texture_material(&kind.sprite, resources, Shader::UNLIT, kind.sprite_color)
+                })
+                .collect();
+
+            let mut ant_entities: Vec<Entity> = Vec::new();
+            let mut spitball_entities: Vec<Entity> = Vec::new();
+
+            // Debug views, cycled with V and L.
+            let mut texture_view = TextureView::Materials;
+            let mut show_ant_directions = false;
+            let mut direction_line_entities: Vec<Entity> = Vec::new();
+            let direction_line_material = resources.get::<AssetStore<Material>>().add(Material {
+                base_color: Color::CYAN,
+                shader: Shader::UNLIT,
+                ..Default::default()
+            });
+
+            // Shown while editing a level.
+            let mut spawn_point_entities: Vec<Entity> = Vec::new();
+            let mut target_marker_entities: Vec<Entity> = Vec::new();
+            let spawn_point_material = resources.get::<AssetStore<Material>>().add(Material {
+                base_color: Color::RED,
+                shader: Shader::UNLIT,
+                ..Default::default()
+            });
+            let target_marker_material = resources.get::<AssetStore<Material>>().add(Material {
+                base_color: Color::YELLOW,
+                shader: Shader::UNLIT,
+                ..Default::default()
+            });
 
             let foot_material = get_texture_material(
                 "assets/foot.png",
@@ -107,25 +230,14 @@ fn main() {
                 Color::WHITE,
             );
 
-            let mut sandbox_mode = false;
-            {
-                world.spawn((
-                    Transform::new()
-                        .with_position((Vec2::new(40.0, 30.0)).extend(-1.0))
-                        .with_scale(Vec3::fill(50.0)),
-                    Mesh::VERTICAL_QUAD,
-                    foot_material.clone(),
-                ));
-
-                world.spawn((
-                    Transform::new()
-                        .with_position((Vec2::new(60.0, 30.0)).extend(-1.0))
-                        .with_scale(Vec3::fill(50.0))
-                        .with_rotation(Quaternion::from_angle_axis(std::f32::consts::PI, Vec3::Y)),
-                    Mesh::VERTICAL_QUAD,
-                    foot_material.clone(),
-                ));
-            }
+            // There's no sandwich art yet, so it's a plain square.
+            let sandwich_material = resources.get::<AssetStore<Material>>().add(Material {
+                base_color: Color::ORANGE,
+                shader: Shader::UNLIT,
+                ..Default::default()
+            });
+            let mut defend_target_entities: Vec<Entity> = Vec::new();
+            let mut synced_defend_targets: Vec<DefendTarget> = Vec::new();
 
             
             let squish_sounds = {
@@ -183,40 +295,28 @@ fn main() {
                 ]
             };
 
-            let strawhand = get_texture_material(
-                "assets/strawhand.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
 
-            let pointed_finger_material = get_texture_material(
-                "assets/pointedfinger.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
-            let spiderhand = get_texture_material(
-                "assets/spiderhand.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
-            let curled_finger_material = get_texture_material(
-                "assets/curledfinger.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
-            let toothpaste_hand = get_texture_material(
-                "assets/toothpastehand.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
+
+            // Every texture an item draws the hand with.
+            let mut hand_art = HashMap::new();
+            for item in CurrentItem::ALL {
+                let Some(art) = items::item(item).hand_art() else {
+                    continue;
+                };
+                for path in [Some(art.idle), art.pressed, art.fired].into_iter().flatten() {
+                    hand_art.entry(path).or_insert_with(|| {
+                        get_texture_material(
+                            path,
+                            resources,
+                            Shader::UNLIT_TRANSPARENT,
+                            Color::WHITE,
+                        )
+                    });
+                }
+            }
 
             let gameover = get_texture_material(
                 "assets/gameover.png",
@@ -239,19 +339,7 @@ fn main() {
                 Color::WHITE,
             );
 
-            let pointed_gun = get_texture_material(
-                "assets/pointedgun.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
-            let gun_fire = get_texture_material(
-                "assets/gunfire.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
             let reticule = get_texture_material(
                 "assets/reticule.png",
@@ -260,12 +348,6 @@ fn main() {
                 Color::RED,
             );
 
-            let nacho_hand = get_texture_material(
-                "assets/nachohand.png",
-                resources,
-                Shader::UNLIT_TRANSPARENT,
-                Color::WHITE,
-            );
 
             let reticule_entity = world.spawn((
                 Transform::new()
@@ -312,79 +394,23 @@ fn main() {
                 Transform::new()
                     .with_position(Vec2::new(22.0, -22.0).extend(-20.0))
                     .with_scale(Vec3::fill(50.0)),
-                curled_finger_material.clone(),
+                hand_art[items::item(CurrentItem::Finger).hand_art().unwrap().idle].clone(),
                 RenderFlags::DEFAULT,
                 //Material::UNLIT,
                 Mesh::VERTICAL_QUAD,
             ));
             let _ = world.set_parent(player_item, player_item_art);
 
-            let mut current_item = CurrentItem::Finger;
-
-            let mut pointer_last_position = Vec3::ZERO;
-
-            let mut started_once = false;
-
-            struct Ant {
-                dir: Vec2,
-                speed: f32,
-                return_mode: bool,
-                health: u8,
-                carrying: Option<TileMaterial>,
-                is_spider: bool,
-                home: Vec2,
-            }
-
-            let a_material = ant_material.clone();
-            let spawn_wave = move |world: &mut World, wave: &Wave| {
-                let mut random = Random::new();
-
-                for i in 0..wave.ant_count {
-                    world.spawn((
-                        Transform::new()
-                            .with_position(
-                                Vec2::new(
-                                    (i as f32 / wave.ant_count as f32) * world_size.x,
-                                    world_size.y - 5.0,
-                                )
-                                .extend(-3.0),
-                            )
-                            .with_scale(Vec3::fill(2.0)),
-                        Ant {
-                            dir: -Vec2::Y,
-                            speed: random.range_f32(0.05..0.2),
-                            return_mode: false,
-                            health: 255,
-                            carrying: None,
-                            is_spider: false,
-                            home: Vec2::ZERO
-                        },
-                        Mesh::VERTICAL_QUAD,
-                        //Material::UNLIT,
-                        a_material.clone(),
-                    ));
-                }
-            };
-
             let mut random = Random::new();
 
-            // grid_world.update_texture(world, resources, grid_world_display);
-
             let mut skip_first = true;
-            let mut toothpaste_squeeze = 2.0;
-
-            let mut pointer_position = Vec3::ZERO;
-            let mut pointer_max_speed = None;
-
-            let player_health_max = 25;
-            let mut player_health = player_health_max;
 
             let mut screen_shake_amount = 0.0;
 
             // Also used for a heal effect
             let mut player_hurt_effect: f32 = 0.0;
 
-            let mut intro_interpolate = 0.0;
+            let mut intro_interpolate = if simulation.started_once { 1.0 } else { 0.0 };
 
             let gun_cooldown_animation_reset = 1.0;
             let mut gun_cooldown_animation = 0.0;
@@ -426,234 +452,7 @@ fn main() {
                 ..Default::default()
             });
 
-            #[derive(Clone, Copy, Debug)]
-            enum SliderOption {
-                Gun,
-                Bullets,
-                Nachos,
-                Toothpaste,
-            }
-
-            struct Slider {
-                line_position: f32,
-                line_dir: f32,
-                line_entity: Entity,
-                targets: Vec<((f32, f32), SliderOption, Entity)>,
-                entities: Vec<Entity>,
-                visible: bool,
-                lost_entries: Vec<(f32, SliderOption, Handle<Material>)>,
-            }
-
-            fn ranges_overlap(a: (f32, f32), b: (f32, f32)) -> bool {
-                let (a_start, a_end) = a;
-                let (b_start, b_end) = b;
-
-                // Check if the ranges overlap
-                a_start <= b_end && b_start <= a_end
-            }
-
-            impl Slider {
-                pub fn add_option(
-                    &mut self,
-                    world: &mut World,
-                    len: f32,
-                    option: SliderOption,
-                    material: Handle<Material>,
-                ) {
-                    let parent = self.entities[0];
-
-                    let mut r = Random::new();
-
-                    let mut start = None;
-                    // Try 20 times to insert
-                    for _ in 0..20 {
-                        let mut overlaps = false;
-                        let proposed_start = r.f32();
-
-                        if proposed_start + len > 1.0 {
-                            continue;
-                        }
-
-                        for t in self.targets.iter() {
-                            let overlaps_here =
-                                ranges_overlap((proposed_start, proposed_start + len), t.0);
-                            if overlaps_here {
-                                overlaps = true;
-                                break;
-                            }
-                        }
-
-                        if !overlaps {
-                            start = Some(proposed_start);
-                            break;
-                        }
-                    }
-                    
-
-                    if let Some(start) = start {
-                        let e = world.spawn((
-                            Transform::new()
-                                .with_position(Vec3::new(-0.5 + start + len / 2.0, -0.0, -1.0))
-                                .with_scale(Vec3::new(len, 1.0, 1.0)),
-                            Mesh::VERTICAL_QUAD,
-                            if self.visible {
-                                RenderFlags::DEFAULT
-                            } else {
-                                RenderFlags::NONE
-                            },
-                            material.clone(),
-                        ));
-                        let _ = world.set_parent(parent, e);
-
-                        self.targets.push(((start, start + len), option, e));
-                    } else {
-                        self.lost_entries.push((len, option, material));
-                    }
-                }
-                pub fn new(
-                    world: &mut World,
-                    parent: Entity,
-                    base_material: Handle<Material>,
-                ) -> Self {
-                    let mut entities = Vec::new();
-                    let base_entity = world.spawn((
-                        Transform::new()
-                            .with_position(Vec3::new(0.0, -20.0, -30.0))
-                            .with_scale(Vec3::new(50.0, 5.0, 1.0)),
-                        Mesh::VERTICAL_QUAD,
-                        RenderFlags::DEFAULT,
-                        base_material,
-                    ));
-                    let _ = world.set_parent(parent, base_entity);
-                    entities.push(base_entity);
-
-                    let line_entity = world.spawn((
-                        Transform::new()
-                            .with_position(Vec3::new(0.0, 0.0, -2.0))
-                            .with_scale(Vec3::new(0.01, 1.4, 1.0)),
-                        Mesh::VERTICAL_QUAD,
-                        Material::UNLIT,
-                        RenderFlags::DEFAULT,
-                    ));
-                    let _ = world.set_parent(base_entity, line_entity);
-                    entities.push(line_entity);
-
-                    let mut s = Self {
-                        line_position: 0.0,
-                        line_dir: 1.0,
-                        line_entity,
-                        targets: Vec::new(),
-                        entities,
-                        visible: true,
-                        lost_entries: Vec::new(),
-                    };
-                    s.move_line(world);
-                    s
-                }
-
-                fn move_line(&mut self, world: &mut World) {
-                    let mut e = world.get::<&mut Transform>(self.line_entity).unwrap();
-                    e.position.x = -0.5 + self.line_position;
-                }
-
-                pub fn progress_line(&mut self, world: &mut World) {
-                    self.line_position += self.line_dir * 0.01;
-
-                    if self.line_position > 1.0 {
-                        self.line_position = 1.0;
-                        self.line_dir = -1.0;
-                    }
-
-                    if self.line_position <= 0.0 {
-                        self.line_position = 0.0;
-                        self.line_dir = 1.0;
-                    }
-
-                    self.move_line(world);
-                }
-
-                pub fn insert_random_lost_entry(&mut self, world: &mut World) {
-                    if !self.lost_entries.is_empty() {
-                        let i = Random::new().range_u32(0..self.lost_entries.len() as _);
-                        let removed = self.lost_entries.remove(i as _);
-                        self.add_option(world, removed.0, removed.1, removed.2);
-                    }
-                }
-
-                pub fn remove_random(&mut self, world: &mut World) {
-                    if !self.targets.is_empty() {
-                        let i = Random::new().range_u32(0..self.targets.len() as _);
-                        let removed: ((f32, f32), SliderOption, Entity) =
-                            self.targets.remove(i as _);
-                        let material =
-                            (*world.get::<&Handle<Material>>(removed.2).unwrap()).clone();
-
-                        let _ = world.despawn(removed.2);
-                        self.lost_entries
-                            .push((removed.0 .1 - removed.0 .0, removed.1, material));
-                    }
-                }
-
-                pub fn stop_line(&mut self, world: &mut World) -> Option<SliderOption> {
-                    if !self.visible {
-                        return None;
-                    }
-
-                    let mut found_target = None;
-
-                    for (i, t) in self.targets.iter().enumerate() {
-                        let overlaps_here = ranges_overlap(
-                            (self.line_position - 0.02, self.line_position + 0.02),
-                            t.0,
-                        );
-
-                        if overlaps_here {
-                            found_target = Some(i);
-                            break;
-                        }
-                    }
-
-                    if let Some(found_target) = found_target {
-                        let removed = self.targets.remove(found_target);
-                        let _ = world.despawn(removed.2);
-                        Some(removed.1)
-                    } else {
-                        None
-                    }
-                }
-
-                pub fn hide(&mut self, world: &mut World) {
-                    self.visible = false;
-                    for entity in self.entities.iter() {
-                        *world.get::<&mut RenderFlags>(*entity).unwrap() = RenderFlags::NONE;
-                    }
-
-                    for (_, _, e) in self.targets.iter() {
-                        *world.get::<&mut RenderFlags>(*e).unwrap() = RenderFlags::NONE;
-                    }
-                }
-
-                pub fn show(&mut self, world: &mut World) {
-                    self.visible = true;
-                    for entity in self.entities.iter() {
-                        *world.get::<&mut RenderFlags>(*entity).unwrap() = RenderFlags::DEFAULT;
-                    }
-
-                    for (_, _, e) in self.targets.iter() {
-                        *world.get::<&mut RenderFlags>(*e).unwrap() = RenderFlags::DEFAULT;
-                    }
-                }
-
-                pub fn clear(&mut self, world: &mut World) {
-                    for (_, _, e) in self.targets.drain(..) {
-                        let _ = world.despawn(e);
-                    }
-                    self.lost_entries.clear();
-                }
-            }
-
-            let mut slider = Slider::new(world, camera, slider_material);
-            slider.hide(world);
+            let mut slider_view = SliderView::new(world, camera, slider_material);
 
             {
                 use kui::*;
@@ -742,203 +541,23 @@ fn main() {
                 bonus_text: String::new(),
             });
 
-            enum WaveTrigger {
-                LowAntCount(usize),
-                FramesElapsedSinceLast(u32),
-            }
-
-            struct Wave {
-                victory_condition: WaveTrigger,
-                frame_delay: usize,
-                ant_count: usize,
-                ui_lines: Vec<String>,
-                bonus_text: String,
-                rummage_options: Vec<SliderOption>,
-            }
-
-            impl Default for Wave {
-                fn default() -> Self {
-                    Self {
-                        victory_condition: WaveTrigger::LowAntCount(0),
-                        frame_delay: 0,
-                        ant_count: 10,
-                        ui_lines: Vec::new(),
-                        rummage_options: Vec::new(),
-                        bonus_text: String::new(),
-                    }
-                }
-            }
-
-            let mut current_wave: i32 = -1;
-            let waves = vec![
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 5,
-                    ui_lines: vec![
-                        "\"The man who retreats...".into(),
-                        "...is no longer a man\"".into(),
-                    ],
-                    rummage_options: vec![
-                        SliderOption::Gun,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 10,
-                    ui_lines: vec!["WAVE 2".into(), "Press 2 to use the straw".into(), "Click to fire. Hold to suck.".into()],
-                    rummage_options: vec![
-                        SliderOption::Toothpaste,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 60 * 1,
-                    ant_count: 20,
-                    ui_lines: vec!["WAVE 3".into(), "Hold spacebar periodically to rummage in your pockets".into()],
-                    rummage_options: vec![
-                        SliderOption::Gun,
-                        SliderOption::Toothpaste,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                        SliderOption::Nachos,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 60 * 5,
-                    ant_count: 40,
-                    ui_lines: vec![
-                        "WAVE 4".into(),
-                        "Use the straw to reach far off targets".into(),
-                        "\"It is not the mountain we conquer\n but ourselves.\"".into(),
-                    ],
-                    rummage_options: vec![
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Nachos,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 500,
-                    ui_lines: vec![
-                        "WAVE 5".into(),
-                        "\"No man is more unhappy than he who never faces adversity.".into(),
-                        "For he is not permitted to prove himself.\"".into(),
-                    ],
-                    rummage_options: vec![
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Toothpaste,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 10,
-                    ui_lines: vec![
-                        "The next wave will by your final battle.\n It will test you. Good luck".into(),
-                    ],
-                    rummage_options: vec![
-                        SliderOption::Toothpaste,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 1000,
-                    ui_lines: vec![
-                        "WAVE 6: THE FINAL BATTLE".into(),
-                    ],
-                    rummage_options: vec![
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                        SliderOption::Bullets,
-                    ],
-                    ..Default::default()
-                },
-                Wave {
-                    victory_condition: WaveTrigger::LowAntCount(0),
-                    frame_delay: 0,
-                    ant_count: 1,
-                    ui_lines: vec![
-                        "the very last ant...".into(),
-                    ],
-                    rummage_options: vec![
-                      
-                    ],
-                    ..Default::default()
-                },
-            ];
-
-            let mut frames_elapsed_since_last_wave = 0;
-
             let mut ui_transparency_animation = 0.0;
 
-            let mut spawn_wave_override = false;
-
-            let mut wave_text_shown = false;
-
-            let mut bullet_count = 0;
-            let mut nacho_crumb_count: i32 = 0;
-            let mut toothpaste_tile_count: i32 = 0;
-            let mut spider_count = 0;
+            // Input that arrives between fixed updates is queued for the next tick.
+            let mut pending_commands: Vec<Command> = Vec::new();
+            let mut pending_actions: Vec<(Action, bool)> = Vec::new();
 
-            let mut rummage_count = 0;
+            let mut gamepads = Gamepads::new();
+            // Where the gamepad has moved the pointer to, until the mouse moves again.
+            let mut gamepad_pointer: Option<Vec3> = None;
+            let mut last_pointer_position_window = (0.0, 0.0);
+            let mut fire_held = false;
 
-            let mut wave_move_on_timer = 0;
-
-            let mut inventory = HashSet::new();
-            // inventory.insert(CurrentItem::Nacho);
-            inventory.insert(CurrentItem::Straw);
-            //inventory.insert(CurrentItem::Gun);
-
-
-            let mut victory = false;
-            let mut defeated = false;
-
-            let  rummage_refresh_reset = 60 * 20;
-            let mut rummage_refresh_timer = 0;
-
-            let mut straw_contents: VecDeque<TileMaterial> = VecDeque::new();
-            let mut straw_cooldown_timer = 0.0;
-            let mut straw_sucking = false;
-
-            struct SpitBall {
-                target: Vec2,
-                material: Vec<TileMaterial>
-            }
+            let mut paused = false;
+            let mut step_once = false;
 
             let mut current_straw_sound = None;
 
-            let mut pointer_held_len = 0;
-            
-
             move |event, world, resources| {
                 if update_ui_with_event::<UIState>(world, resources, event) {
                     return;
@@ -946,7 +565,7 @@ fn main() {
                 match event {
                     Event::Draw => {
                         {
-                            if victory {
+                            if simulation.victory {
                                 *world.get::<&mut RenderFlags>(victoryscreen).unwrap() =
                                     RenderFlags::DEFAULT;
 
@@ -961,7 +580,7 @@ fn main() {
                                 ui_state.bonus_text = String::new();
                             }
 
-                            if started_once {
+                            if simulation.started_once {
                                 *world.get::<&mut RenderFlags>(titlescreen).unwrap() =
                                     RenderFlags::NONE;
                             }
@@ -972,8 +591,9 @@ fn main() {
                             let mut materials = resources.get::<AssetStore<Material>>();
                             let material = materials.get_mut(&material_handle);
 
+                            let player_health = simulation.player_health;
                             let health_lerp =
-                                (1.0 - (player_health as f32 / player_health_max as f32)).clamp(0.0, 1.0);
+                                (1.0 - (player_health as f32 / PLAYER_HEALTH_MAX as f32)).clamp(0.0, 1.0);
                             let mut color = Color::interpolate(
                                 Color::WHITE,
                                 Color::RED,
@@ -994,31 +614,32 @@ fn main() {
                                 );
                             };
 
-                            if player_health > player_health_max {
+                            if player_health > PLAYER_HEALTH_MAX {
                                 color = Color::interpolate(
                                     color,
                                     Color::GREEN,
-                                    ((player_health as f32 - player_health_max as f32) / 50.0).clamp(0.0, 1.0),
+                                    ((player_health as f32 - PLAYER_HEALTH_MAX as f32) / 50.0).clamp(0.0, 1.0),
                                 );
                             }
 
                             material.base_color = color;
 
-                            let foot_material = materials.get_mut(&foot_material);
-                            foot_material.base_color = color;
-
-                            let ant_material = materials.get_mut(&ant_material);
+                            materials.get_mut(&foot_material).base_color = color;
+                            materials.get_mut(&sandwich_material).base_color =
+                                Color::interpolate(Color::ORANGE, color, 0.5);
 
-                            if !victory && !sandbox_mode && player_health <= 0 {
-                                if !defeated {
-                                    let mut audio_manager: std::sync::RwLockWriteGuard<'_, AudioManager> = resources.get::<AudioManager>();
-                                    let sounds = resources.get::<AssetStore<Sound>>();
-                                    let sound = sounds.get(random.select_from_slice(&defeat_sounds));
-                                    audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.8..1.2));
-                                    defeated = true;
-                                }
+                            for ((_, kind), enemy_material) in
+                                simulation.enemy_kinds.iter().zip(enemy_materials.iter())
+                            {
+                                materials.get_mut(enemy_material).base_color =
+                                    if simulation.is_game_over() {
+                                        Color::WHITE
+                                    } else {
+                                        kind.sprite_color
+                                    };
+                            }
 
-                                ant_material.base_color = Color::WHITE;
+                            if simulation.is_game_over() {
                                 *world.get::<&mut RenderFlags>(gameoverscreen).unwrap() =
                                     RenderFlags::DEFAULT;
 
@@ -1032,7 +653,6 @@ fn main() {
                                 ui_state.bonus_text = String::new();
 
                             } else {
-                                ant_material.base_color = Color::BLACK;
                                 *world.get::<&mut RenderFlags>(gameoverscreen).unwrap() =
                                     RenderFlags::NONE;
                                 *world.get::<&mut RenderFlags>(player_item_art).unwrap() =
@@ -1042,7 +662,15 @@ fn main() {
                             }
                         }
 
-                        grid_world.update_texture(world, resources, grid_world_display);
+                        simulation
+                            .grid_world
+                            .update_texture(
+                                world,
+                                resources,
+                                grid_world_display,
+                                texture_view,
+                                &simulation.materials,
+                            );
 
                         let screen_shake = Vec2::new(
                             random.range_f32(-screen_shake_amount..screen_shake_amount),
@@ -1056,1238 +684,426 @@ fn main() {
                         screen_shake_amount *= 0.90;
                         player_hurt_effect *= 0.94;
                     }
-                    Event::KappEvent(KappEvent::KeyDown { key: Key::R, .. }) => {
-                        screen_shake_amount += 3.0;
-                        player_health -= 1;
-                        println!("HEALTH: {:?}", player_health);
-                        grid_world.reset_pheromones();
+                    Event::KappEvent(KappEvent::KeyDown { key, .. }) => {
+                        if let Some(action) = bindings.action(Binding::key(*key)) {
+                            pending_actions.push((action, true));
+                        }
                     }
-                    Event::KappEvent(KappEvent::KeyDown { key: Key::K, .. }) => {
-                        // Enter sandbox mode
-                        if !started_once {
-                            inventory.insert(CurrentItem::Gun);
-                            inventory.insert(CurrentItem::Toothpaste);
-                            inventory.insert(CurrentItem::Straw);
-
-                            bullet_count = i32::MAX;
-                            nacho_crumb_count = i32::MAX;
-                            sandbox_mode = true;
-                            started_once = true;
-                            grid_world.neutral_pheremones();
-                            spawn_ants(world, 200);
-                            intro_interpolate = 100.0;
-                            toothpaste_tile_count = i32::MAX;
-                            spider_count = i32::MAX;
-
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text = 200.0;
-                            ui_state.bonus_text = "Welcome to SANDBOX MODE".into();
+                    Event::KappEvent(KappEvent::KeyUp { key, .. }) => {
+                        if let Some(action) = bindings.action(Binding::key(*key)) {
+                            pending_actions.push((action, false));
                         }
                     }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Space, ..
-                    })
-                    | Event::KappEvent(KappEvent::KeyUp {
-                        key: Key::Space, ..
-                    }) => {
-                        let key_up = match event {
-                            Event::KappEvent(KappEvent::KeyUp {
-                                key: Key::Space, ..
-                            }) => true,
-                            _ => false,
+                    Event::KappEvent(KappEvent::Scroll { delta_y, .. }) if *delta_y != 0.0 => {
+                        let wheel = if *delta_y > 0.0 {
+                            Binding::WheelUp
+                        } else {
+                            Binding::WheelDown
                         };
-
-                        if started_once == false && !key_up {
-                            grid_world.reset_pheromones();
-                            started_once = true;
+                        if let Some(action) = bindings.action(wheel) {
+                            pending_actions.push((action, true));
+                        }
+                    }
+                    Event::FixedUpdate => {
+                        if skip_first {
+                            skip_first = false;
                             return;
                         }
 
-                        // RESET AND RESTART THE WORLD
-                        if !sandbox_mode && player_health <= 0 {
-                            rummage_refresh_timer = 0;
-
-                            current_wave = -1;
-                            let mut to_despawn = Vec::new();
-                            for (e, _) in world.query::<(&Ant,)>().iter() {
-                                to_despawn.push(e);
+                        {
+                            let mut ui_state = resources.get::<UIState>();
+                            ui_state.display_bonus_text -= 0.1;
+                            if ui_state.display_bonus_text < 0.0 {
+                                ui_state.bonus_text = String::new();
                             }
-                            for e in to_despawn {
-                                let _ = world.despawn(e);
+                        }
+
+                        ui_transparency_animation -= 0.005;
+                        gun_cooldown_animation -= 0.1;
+
+                        for (button, pressed) in gamepads.poll() {
+                            if let Some(action) = bindings.action(Binding::Gamepad(button)) {
+                                pending_actions.push((action, pressed));
                             }
+                        }
 
-                            intro_interpolate = 0.0;
-                            grid_world.initial_terrain_setup();
-                            player_health = player_health_max;
-                            frames_elapsed_since_last_wave = 0;
-                            slider.clear(world);
-                            wave_text_shown = false;
-                            rummage_count = 0;
-                            bullet_count = 0;
-                            nacho_crumb_count = 0;
-                            toothpaste_tile_count = 0;
-                            straw_contents.clear();
-                            straw_sucking = false;
-                            defeated = false;
-                            current_item = CurrentItem::Finger;
-                            
-                            inventory.clear();
-                            inventory.insert(CurrentItem::Straw);
-
-                            slider.hide(world);
-                        } else {
-                            let mut ui_state: std::sync::RwLockWriteGuard<'_, UIState> =
-                                resources.get::<UIState>();
-
-                            let option = slider.stop_line(world);
-
-                            match option {
-                                Some(o) => {
-                                    match o {
-                                        SliderOption::Bullets => {
-                                            ui_state.bonus_text = "Found 20 BULLETS".into();
-                                            bullet_count += 20;
-                                            ui_state.display_bonus_text = 10.0;
-                                        }
-                                        SliderOption::Gun => {
-                                            inventory.insert(CurrentItem::Gun);
-                                            bullet_count += 10;
-                                            ui_state.bonus_text =
-                                                "Found a GUN in my pocket\nPress 4 to equip".into();
-                                            ui_state.display_bonus_text = 60.0;
-                                        }
-                                        SliderOption::Nachos => {
-                                            nacho_crumb_count += 6;
-                                            ui_state.bonus_text =
-                                                "\"Chip crumbs. Perfect. \"\nPress 5 to equip"
-                                                    .into();
-                                            ui_state.display_bonus_text = 20.0;
-                                        }
-                                        SliderOption::Toothpaste => {
-                                            inventory.insert(CurrentItem::Toothpaste);
-
-                                            toothpaste_tile_count += 200;
-                                            ui_state.bonus_text =
-                                                "\"Toothpaste. Minty fresh barriers. \"\nPress 3 to equip"
-                                                    .into();
-                                            ui_state.display_bonus_text = 20.0;
+                        for (action, pressed) in std::mem::take(&mut pending_actions) {
+                            if action.is_cheat() && !dev_mode {
+                                continue;
+                            }
+                            match action {
+                                Action::Rummage => {
+                                    pending_commands.push(Command::Rummage { key_up: !pressed });
+                                }
+                                // Fire is read every tick below, and nothing else acts on release.
+                                Action::Fire => {}
+                                _ if !pressed => {}
+                                Action::Hotbar(slot) => {
+                                    pending_commands.extend(select_hotbar_slot(&simulation, slot));
+                                }
+                                Action::NextItem => pending_commands
+                                    .push(Command::SelectItem(simulation.hotbar.cycle(1))),
+                                Action::PreviousItem => pending_commands
+                                    .push(Command::SelectItem(simulation.hotbar.cycle(-1))),
+                                Action::ForceWave => pending_commands.push(Command::ForceWave),
+                                Action::EnterSandbox => {
+                                    pending_commands.push(Command::EnterSandbox)
+                                }
+                                Action::DebugHurt => pending_commands.push(Command::DebugHurt),
+                                Action::Save => match &edit_path {
+                                    Some(path) => save_level(path, &simulation),
+                                    None => pending_commands.push(Command::Save),
+                                },
+                                Action::Load if edit_path.is_some() => {
+                                    load_level(edit_path.as_ref().unwrap(), &mut simulation);
+                                }
+                                Action::Load => {
+                                    if load_save(&save_path, &config, &mut simulation) {
+                                        intro_interpolate =
+                                            if simulation.started_once { 1.0 } else { 0.0 };
+
+                                        // A recording can't play back a load, so it ends here.
+                                        if recorder.take().is_some() {
+                                            info!("Stopped recording because a save was loaded");
                                         }
+                                        replay_ticks = None;
                                     }
-                                    //slider.remove_random(world);
-                                    rummage_count -= 1;
                                 }
-
-                                None => {}
-                            }
-
-                            if slider.visible {
-                                ui_state.display_bonus_text = 10.0;
-                                slider.hide(world);
-
-                                /*
-                                if rummage_count > 0 {
-                                    ui_state.bonus_text =
-                                        "I still feel an urge to rummage...".into();
-                                    ui_state.display_bonus_text = 20.0;
+                                Action::CycleView => {
+                                    texture_view = texture_view.next();
+                                    info!("VIEW: {:?}", texture_view);
                                 }
-                                */
-                            } else if !key_up {
-                                slider.show(world);
-
-                                /*
-                                if !slider.targets.is_empty()
-                                /*&& rummage_count > 0*/
-                                {
-                                    slider.show(world);
-                                } else {
-                                    ui_state.bonus_text = "I'll rummage after more ants die".into();
-                                    ui_state.display_bonus_text = 20.0;
+                                Action::ToggleDirections => {
+                                    show_ant_directions = !show_ant_directions;
+                                }
+                                Action::Inspect => pending_commands.extend([
+                                    Command::GrantItem(CurrentItem::Inspector),
+                                    Command::SelectItem(CurrentItem::Inspector),
+                                ]),
+                                Action::SetHealth(health) => {
+                                    pending_commands.push(Command::SetHealth(health))
+                                }
+                                Action::GrantItem(item) => {
+                                    pending_commands.push(Command::GrantItem(item))
                                 }
-                                */
+                                Action::SkipToWave(wave) => {
+                                    pending_commands.push(Command::SkipToWave(wave))
+                                }
+                                Action::Sandbox(command) => {
+                                    pending_commands.push(Command::Sandbox(command))
+                                }
+                                Action::NextSandboxTool => {
+                                    let tool = simulation.sandbox_tools.tool.next();
+                                    pending_commands
+                                        .push(Command::Sandbox(SandboxCommand::Tool(tool)));
+                                }
+                                Action::Pause if simulation.sandbox_mode => {
+                                    paused = !paused;
+                                    info!("{}", if paused { "PAUSED" } else { "UNPAUSED" });
+                                }
+                                Action::Step if simulation.sandbox_mode => step_once = true,
+                                Action::Pause | Action::Step => {}
                             }
-                            println!("STOPPED ON: {:?}", option);
-                        }
-                    }
-                    Event::KappEvent(KappEvent::KeyDown { key: Key::N, .. }) => {
-                        spawn_wave_override = true;
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit1, ..
-                    }) => {
-                        current_item = CurrentItem::Finger;
-                    }
-                    Event::KappEvent(KappEvent::KeyDown { key: Key::I, .. }) => {
-                        current_item = CurrentItem::Inspector;
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit3, ..
-                    }) => {
-                        let item = CurrentItem::Toothpaste;
-                        if inventory.contains(&item) {
-                            current_item = item;
-                        } else {
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text = 50.0;
-                            ui_state.bonus_text = "I'm out of that".into();
-                        }
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit4, ..
-                    }) => {
-                        let item = CurrentItem::Gun;
-                        if inventory.contains(&item) {
-                            current_item = item;
-                        }
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit5, ..
-                    }) => {
-                        let item = CurrentItem::Nacho;
-                        if nacho_crumb_count > 0 {
-                            current_item = item;
-                        } else {
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text = 50.0;
-                            ui_state.bonus_text = "I'm out of that".into();
-                        }
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit2, ..
-                    }) => {
-                        let item = CurrentItem::Straw;
-                        
-                        if inventory.contains(&item) {
-                            current_item = item;
-                        } else {
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text = 50.0;
-                            ui_state.bonus_text = "I'm out of that".into();
-                        }
-                    }
-                    Event::KappEvent(KappEvent::KeyDown {
-                        key: Key::Digit6, ..
-                    }) => {
-                        let item = CurrentItem::Spider;
-                        if spider_count > 0 {
-                            current_item = item;
-                        } else {
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text = 50.0;
-                            ui_state.bonus_text = "I'm out of that".into();
-                        }
-                    }
-                    Event::FixedUpdate => {
-                        if skip_first {
-                            skip_first = false;
-                            return;
                         }
 
-                        let mut to_despawn: Vec<Entity> = Vec::new();
-
-                        let mut audio_manager: std::sync::RwLockWriteGuard<'_, AudioManager> = resources.get::<AudioManager>();
-
-                        slider.progress_line(world);
+                        // A paused sandbox only moves on when it's stepped.
+                        let run_tick = !(paused && simulation.sandbox_mode)
+                            || std::mem::take(&mut step_once);
+
+                        let (pointer_position_window, tick_input) = {
+                            let input = resources.get::<Input>();
+                            let pointer_position_window: (f64, f64) = input.pointer_position();
+                            let pointer_position_new = get_pointer_world_position(
+                                world,
+                                &resources,
+                                camera_child,
+                                pointer_position_window.0 as _,
+                                pointer_position_window.1 as _,
+                            );
 
-                        {
-                            let mut ui_state = resources.get::<UIState>();
-                            ui_state.display_bonus_text -= 0.1;
-                            if ui_state.display_bonus_text < 0.0 {
-                                ui_state.bonus_text = String::new();
+                            if pointer_position_window != last_pointer_position_window {
+                                last_pointer_position_window = pointer_position_window;
+                                gamepad_pointer = None;
                             }
-                        }
 
-                        ui_transparency_animation -= 0.005;
-
-                      
-                        if player_health > 0 && !sandbox_mode && !victory && started_once {
-                            
-                            rummage_refresh_timer -= 1;
-                            if rummage_refresh_timer <= 0 && !slider.visible {
-                                let random_amount_to_stash = random.range_u32(2..10);
-                                // for _ in 0..random_amount_to_stash {
-                                //     slider.remove_random(world);
-                                // }
-
-                                for _ in 0..(random_amount_to_stash.saturating_sub(4)) {
-                                    slider.insert_random_lost_entry(world);
-                                }
-
-                                /*
-                                let random_new_items = random.range_u32(0..4);
-                                for _ in 0..random_new_items {
-                                    let new_type = random.select_from_slice(&[SliderOption::Bullets, SliderOption::Nachos, SliderOption::Toothpaste]);
-                                    
-                                        match new_type {
-                                            SliderOption::Bullets => {
-                                                slider.add_option(
-                                                    world,
-                                                    0.02,
-                                                    SliderOption::Bullets,
-                                                    bullets_slider_material.clone(),
-                                                );
-                                            }
-                                            SliderOption::Nachos => {
-                                                slider.add_option(
-                                                    world,
-                                                    0.02,
-                                                    SliderOption::Nachos,
-                                                    nacho_slider_material.clone(),
-                                                );
-                                            }
-                                            SliderOption::Toothpaste => {
-                                                slider.add_option(
-                                                    world,
-                                                    0.15,
-                                                    SliderOption::Toothpaste,
-                                                    toothpaste_slider_color.clone(),
-                                                );
-                                            }
-                                            _ => unreachable!()
-                                        }   
-                                }
-                                */
-
-                                if !slider.targets.is_empty() {
-                                    let mut ui_state = resources.get::<UIState>();
-                                   // ui_state.display_bonus_text = 50.0;
-                                   // ui_state.bonus_text =
-                                   //     "\"I should rummage again...\" [SPACEBAR]".into();
-                                }
-                                rummage_refresh_timer = rummage_refresh_reset;
+                            // The stick moves a pointer the hand follows, the same as the mouse.
+                            let stick = gamepads.left_stick();
+                            if stick != Vec2::ZERO {
+                                let p = gamepad_pointer.unwrap_or(pointer_position_new)
+                                    + (stick * GAMEPAD_POINTER_SPEED).extend(0.0);
+                                gamepad_pointer = Some(Vec3::new(
+                                    p.x.clamp(0.0, world_size.x),
+                                    p.y.clamp(0.0, world_size.y),
+                                    0.0,
+                                ));
                             }
-                            
-
-                            // Trigger waves
-                            frames_elapsed_since_last_wave += 1;
 
-                            wave_move_on_timer += 1;
+                            let fire_was_held = fire_held;
+                            fire_held = bindings.bindings(Action::Fire).iter().any(|b| match b {
+                                Binding::Key(key) => input.key(key.0),
+                                Binding::Gamepad(button) => gamepads.is_pressed(*button),
+                                Binding::WheelUp | Binding::WheelDown => false,
+                            });
 
-                            let current_wave_done;
-                            if current_wave == -1 {
-                                current_wave_done = true;
-                            } else {
-                                if let Some(wave) = waves.get(current_wave as usize) {
-                                    let trigger_met = match wave.victory_condition {
-                                        WaveTrigger::LowAntCount(c) => {
-                                            let mut count = 0;
-                                            for (_, (_, t)) in
-                                                world.query::<(&Ant, &Transform)>().iter()
-                                            {
-                                                if wave_move_on_timer > 60 * 30 {
-                                                    if t.position.y < world_size.y - 10.0 {
-                                                        count += 1;
-                                                    }
-                                                } else {
-                                                    count += 1;
-                                                }
-                                            }
-                                            c >= count
-                                        }
-                                        WaveTrigger::FramesElapsedSinceLast(c) => {
-                                            c <= frames_elapsed_since_last_wave
-                                        }
-                                    };
-                                    current_wave_done = trigger_met;
+                            let tick_input = TickInput {
+                                pointer: gamepad_pointer.unwrap_or(pointer_position_new),
+                                pointer_down: input.pointer_button_down(PointerButton::Primary)
+                                    || (fire_held && !fire_was_held),
+                                pointer_held: input.pointer_button(PointerButton::Primary)
+                                    || fire_held,
+                                pointer_released: input
+                                    .pointer_button_released(PointerButton::Primary)
+                                    || (!fire_held && fire_was_held),
+                                // Commands wait for the next tick that runs.
+                                commands: if run_tick {
+                                    std::mem::take(&mut pending_commands)
                                 } else {
-                                    current_wave_done = true;
-                                }
-                            }
-                            
-
-                            if current_wave_done || spawn_wave_override {
-                                spawn_wave_override = false;
-                                if let Some(wave) = waves.get((current_wave + 1) as usize) {
-                                    if !wave_text_shown {
-                                        ui_transparency_animation = 1.0;
-
-                                        let mut ui_state = resources.get::<UIState>();
-                                        ui_state.ui_lines = wave.ui_lines.clone();
-                                    }
-
-                                    if frames_elapsed_since_last_wave > wave.frame_delay as _ {
-                                        wave_move_on_timer = 0;
-
-                                        println!("SPAWNING WAVE: {:?}", current_wave);
-                                        frames_elapsed_since_last_wave = 0;
-                                        current_wave += 1;
-                                        spawn_wave(world, wave);
-                                        rummage_refresh_timer = 0;
-
-                                        // Remove everything but it may come back.
-                                        for _ in 0..slider.targets.len() {
-                                            slider.remove_random(world);
-                                        }
-
-                                        for option in wave.rummage_options.iter() {
-                                            match option {
-                                                SliderOption::Gun => {
-                                                    slider.add_option(
-                                                        world,
-                                                        0.3,
-                                                        SliderOption::Gun,
-                                                        gun_slider_material.clone(),
-                                                    );
-                                                }
-                                                SliderOption::Bullets => {
-                                                    slider.add_option(
-                                                        world,
-                                                        0.02,
-                                                        SliderOption::Bullets,
-                                                        bullets_slider_material.clone(),
-                                                    );
-                                                }
-                                                SliderOption::Nachos => {
-                                                    slider.add_option(
-                                                        world,
-                                                        0.02,
-                                                        SliderOption::Nachos,
-                                                        nacho_slider_material.clone(),
-                                                    );
-                                                }
-                                                SliderOption::Toothpaste => {
-                                                    slider.add_option(
-                                                        world,
-                                                        0.15,
-                                                        SliderOption::Toothpaste,
-                                                        toothpaste_slider_color.clone(),
-                                                    );
-                                                }
-                                            }
-                                        }
-
-                                        if wave.bonus_text.len() > 0 {
-                                            let mut ui_state = resources.get::<UIState>();
-                                            ui_state.display_bonus_text = 50.0;
-                                            ui_state.bonus_text = wave.bonus_text.clone();
-                                        } else if slider.targets.len() > 0 {
-                                           // let mut ui_state = resources.get::<UIState>();
-                                           // ui_state.display_bonus_text = 50.0;
-                                           // ui_state.bonus_text =
-                                           //     "[Hold SPACE to rummage in pockets]".into();
-                                        }
-
-                                        let random_amount = random.range_u32(1..10);
-                                        for _ in 0..random_amount {
-                                            slider.insert_random_lost_entry(world);
-                                        }
-
-                                        rummage_count = 2;
-
-                                        wave_text_shown = false;
+                                    Vec::new()
+                                },
+                            };
+                            (pointer_position_window, tick_input)
+                        };
 
-                                        ui_transparency_animation = 1.0;
-                                    }
-                                } else {
-                                    // No more waves!
-                                    victory = true;
+                        // New waves partway through would play back differently,
+                        // so they wait until nothing's being recorded or replayed.
+                        wave_reload_timer -= 1;
+                        if wave_reload_timer <= 0 && recorder.is_none() && replay_ticks.is_none() {
+                            wave_reload_timer = 60;
+                            match wave_file.reload_if_changed() {
+                                Some(Ok(waves)) => {
+                                    info!("RELOADED WAVES FROM {:?}", wave_file.path);
+                                    let waves = with_default_level(waves, &args.level);
+                                    simulation.levels = level::load_wave_levels(&waves);
+                                    simulation.waves = waves;
+                                }
+                                Some(Err(e)) => {
+                                    warn!(
+                                        "Could not reload waves from {:?}: {}",
+                                        wave_file.path, e
+                                    );
                                 }
+                                None => {}
                             }
                         }
 
-                        gun_cooldown_animation -= 0.1;
-
-                        // Intro Sequence Stuff
-
-                        if started_once && !sandbox_mode {
-                            intro_interpolate += 0.002;
+                        // While a recording plays the live input is ignored.
+                        let tick_input = match replay_ticks.as_mut().filter(|_| run_tick) {
+                            Some(ticks) => match ticks.next() {
+                                Some(recorded) => recorded,
+                                None => {
+                                    info!("REPLAY FINISHED");
+                                    replay_ticks = None;
+                                    tick_input
+                                }
+                            },
+                            None => tick_input,
+                        };
 
-                            if intro_interpolate < 1.0 {
-                                let mut camera: RefMut<'_, Transform> =
-                                    world.get::<&mut Transform>(camera).unwrap();
-                                let curve = koi3::animation_curves::smooth_step(intro_interpolate);
-                                camera.position.y = camera_max + (camera_min - camera_max) * curve;
+                        let mut events = Vec::new();
+                        if run_tick {
+                            if let Some(Err(e)) = recorder.as_mut().map(|r| r.record(&tick_input))
+                            {
+                                warn!("Stopped recording: {}", e);
+                                recorder = None;
                             }
-                            resources.get::<UIState>().transparency =
-                                intro_interpolate.min(1.0 - ui_transparency_animation);
-                        }
-
-                        let input: &mut std::sync::RwLockWriteGuard<'_, kapp::StateTracker> =
-                            &mut resources.get::<Input>();
-                        let pointer_position_window: (f64, f64) = input.pointer_position();
-                        let pointer_position_new = get_pointer_world_position(
-                            world,
-                            &resources,
-                            camera_child,
-                            pointer_position_window.0 as _,
-                            pointer_position_window.1 as _,
-                        );
-
-                        let max_pointer_speed = pointer_max_speed.unwrap_or(100.0);
-
-                        // Adjust pointer speed
-                        {
-                            //  if let Some(pointer_max_speed) = pointer_max_speed {
-                            let dir = pointer_position_new - pointer_position;
-                            let length = dir.length();
-
-                            let max_scale = (length / 200.0).clamp(0.0, 1.0);
-
-                            let amount = max_scale * max_pointer_speed;
 
-                            if length != 0.0 {
-                                let dir = dir / length;
-                                pointer_position += dir * amount;
-                            }
+                            events = simulation.step(&tick_input);
                         }
-                        // } else {
-                        //     pointer_position = pointer_position_new;
-                        // }
-
-                        match current_item {
-                            CurrentItem::Gun | CurrentItem::Straw => {
-                                world
-                                    .get::<&mut Transform>(reticule_entity)
-                                    .unwrap()
-                                    .position = pointer_position_new.xy().extend(-10.0);
-
-                                *world.get::<&mut RenderFlags>(reticule_entity).unwrap() =
-                                    RenderFlags::DEFAULT;
-                            }
-                            _ => {
-                                *world.get::<&mut RenderFlags>(reticule_entity).unwrap() =
-                                    RenderFlags::NONE;
-                            }
-                        }
-
-                        let max_hand_reach = 130.0;
-                        if player_health > 0 || sandbox_mode {
-                            match current_item {
-                                CurrentItem::Spider => {
-                                    if !sandbox_mode {
-                                        pointer_position.y = pointer_position.y.min(max_hand_reach);
-                                    }
-
-                                    *world.get::<&mut Handle<Material>>(player_item_art).unwrap() =
-                                        spiderhand.clone();
-                                
-                                    if input.pointer_button_down(PointerButton::Primary) {
-                                        if spider_count > 0 {
-                                            spider_count -= 1;
-                                            world.spawn((
-                                                Transform::new().with_position(pointer_position).with_scale(Vec3::fill(2.0)),
-                                                Ant {
-                                                    dir: -Vec2::Y,
-                                                    speed: random.range_f32(0.05..0.2),
-                                                    return_mode: false,
-                                                    health: 255,
-                                                    carrying: None,
-                                                    is_spider: true,
-                                                    home: pointer_position.xy()
-                                                },
-                                                Mesh::VERTICAL_QUAD,
-                                                //Material::UNLIT,
-                                                spider_material.clone(),
-                                            ));
-                                        }
-                                    }
-                                }
-                                CurrentItem::Straw => {
-                                    *world.get::<&mut Handle<Material>>(player_item_art).unwrap() =
-                                        strawhand.clone();
-                                    
-                                    let mut origin = pointer_position;
-                                    if !sandbox_mode {
-                                        origin.y = pointer_position.y.min(max_hand_reach);
-                                    }
-                                    let radius = 3.0;
-                                    let height_increments = 30;
-
-                                    let tiles_in_spitball = 16;
-                                    if input.pointer_button_released(PointerButton::Primary) && pointer_held_len < 20 {
-                                        println!("CAPACITY: {:?}", straw_contents.len());
-                                        if !straw_sucking && !straw_contents.is_empty() {
-                                            // Shoot back material
-                                            
-                                            let mut material = Vec::new();
-
-                                            for _ in 0..tiles_in_spitball {
-                                                if let Some(mat) = straw_contents.pop_back() {
-                                                    material.push(mat);
-                                                }
-                                            }
 
-                                            world.spawn((
-                                                Transform::new().with_position(origin.xy().extend(-8.0)).with_scale(Vec3::fill(2.0)),
-                                                Mesh::VERTICAL_CIRCLE,
-                                                Material::UNLIT,
-                                                SpitBall {
-                                                    target: pointer_position_new.xy(),
-                                                    material
-                                                }
-                                            ));
-
-                                            {
-                                                let sounds = resources.get::<AssetStore<Sound>>();
-                                                let sound = sounds.get(random.select_from_slice(&spit_sounds));
-                                                audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.8..1.2));
-                                            }
-                                        }
-                                    }
-
-                                    let straw_capacity = tiles_in_spitball * 15;
-
-                                    if input.pointer_button(PointerButton::Primary) && pointer_held_len > 20 {
-                                        if straw_contents.len() < straw_capacity || straw_sucking {
-                                            if current_straw_sound.is_none() {
-                                                let sounds = resources.get::<AssetStore<Sound>>();
-                                                let sound = sounds.get(&suck_sound);
-                                                
-                                                current_straw_sound = Some(audio_manager
-                                                    .play_one_shot_oddio(oddio::MonoToStereo::new(
-                                                        oddio::Cycle::new(sound.frames.clone())
-                                                )));
-                                            }
-
-                                            if straw_contents.len() < straw_capacity {
-                                                for (e, (transform, ant)) in
-                                                    world.query::<(&mut Transform, &mut Ant)>().iter() {
-                                                        let dir = transform.position - origin;
-                                                        let distance = dir.length();
-                                                        let normalized_dir = dir / distance;
-
-                                                        if distance < 2.0 {
-                                                            to_despawn.push(e);
-
-                                                            player_hurt_effect += 1.0;
-                                                            screen_shake_amount += 2.0;
-                                                            player_health -= 1;
-
-                                                            let sounds = resources.get::<AssetStore<Sound>>();
-                                                            let sound = sounds.get(random.select_from_slice(&squish_sounds));
-                                                            audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.3..0.5));
-                            
-
-                                                            for _ in 0..10 {
-                                                                straw_contents.push_back(TileMaterial::AntBody);
-                                                            }
-                                                        }
-
-                                                        let suck_radius = 10.0;
-                                                        if distance < suck_radius {
-                                                            transform.position -= normalized_dir * 0.9 * (1.0 - distance / suck_radius);
-                                                        }
-                                                }
-                                            }
-                                            // TODO: Check for ants
-                                            straw_sucking = true;
-                                            grid_world.for_tile_in_radius(
-                                                origin.xy(),
-                                                radius,
-                                                |i, _, t| {
-
-                                                    // Heal from chip crumbs
-                                                    if t.material == TileMaterial::Nacho {
-                                                        player_hurt_effect -= 1.0;
-                                                        screen_shake_amount += 0.1;
-                                                        if player_health < 50 {
-                                                            player_health += 4;
-                                                        }
-                                                        t.material = TileMaterial::Earth;
-                                                    }
-
-                                                    if straw_contents.len() < straw_capacity && random.f32() > 0.96 {
-                                                        if t.height > height_increments as u8 {
-                                                            t.height = t.height.saturating_sub(height_increments * 1.4 as u8);
-                                                            straw_contents.push_back(t.material);
-                                                        }
-
-                                                    }
-                                                    false
-                                                },
-                                            );
-                                        }
-                                    } else {
-                                        straw_sucking = false;
-                                    }
-
-                                    if ! straw_sucking || straw_contents.len() >= straw_capacity {
-                                        if let Some(mut current_straw_sound) = current_straw_sound.take() {
-                                            current_straw_sound.control::<oddio::Stop<_>, _>().stop();
-                                        }
-                                    }
+                        {
+                            let mut audio_manager: std::sync::RwLockWriteGuard<'_, AudioManager> =
+                                resources.get::<AudioManager>();
+                            let sounds = resources.get::<AssetStore<Sound>>();
+                            let mut ui_state = resources.get::<UIState>();
 
-                                    pointer_position = origin;
-                                    
-                                }
-                                CurrentItem::Inspector => {
-                                    if input.pointer_button_down(PointerButton::Primary) {
-                                        let radius = 1.0;
-
-                                        grid_world.for_tile_in_radius(
-                                            pointer_position_new.xy(),
-                                            radius,
-                                            |i, _, t| {
-                                                println!("P: {:?}", t.pheremone_level);
-                                                println!("H: {:?}", t.height);
-
-                                                false
-                                            },
-                                        );
+                            for event in events {
+                                match event {
+                                    SimEvent::AntKilled => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &squish_sounds, 1.0..2.0);
                                     }
-                                }
-                                CurrentItem::Nacho => {
-                                    if !sandbox_mode {
-                                        pointer_position.y = pointer_position.y.min(max_hand_reach);
+                                    SimEvent::AntSucked => {
+                                        player_hurt_effect += 1.0;
+                                        screen_shake_amount += 2.0;
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &squish_sounds, 0.3..0.5);
                                     }
-
-                                    *world.get::<&mut Handle<Material>>(player_item_art).unwrap() =
-                                        nacho_hand.clone();
-
-                                    if input.pointer_button_down(PointerButton::Primary) {
-                                        let radius = 2.0;
-
-                                        let mut tile_to_drop_pheromone = Vec::new();
-                                        grid_world.for_tile_in_radius(
-                                            pointer_position_new.xy(),
-                                            radius,
-                                            |i, _, t| {
-                                                if nacho_crumb_count > 0
-                                                    && t.material != TileMaterial::Nacho
-                                                    && random.f32() > 0.90
-                                                {
-                                                    t.material = TileMaterial::Nacho;
-                                                    nacho_crumb_count -= 1;
-                                                    tile_to_drop_pheromone.push(i)
-                                                }
-
-                                                false
-                                            },
-                                        );
-
-                                        let nacho_pheromone_radius = 20.0;
-                                        for t in tile_to_drop_pheromone {
-                                            grid_world.for_tile_in_radius(
-                                                pointer_position_new.xy(),
-                                                nacho_pheromone_radius,
-                                                |i, r, t| {
-                                                    t.pheremone_level -=
-                                                        (nacho_pheromone_radius - r) as i16;
-
-                                                    false
-                                                },
-                                            );
-                                        }
+                                    SimEvent::AntReachedFeet => {
+                                        screen_shake_amount += 1.0;
+                                        player_hurt_effect += 1.0;
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &squish_sounds, 0.3..0.5);
                                     }
-                                    if nacho_crumb_count <= 0 {
-                                        inventory.remove(&CurrentItem::Nacho);
-                                        current_item = CurrentItem::Finger;
+                                    SimEvent::NachoSucked => {
+                                        player_hurt_effect -= 1.0;
+                                        screen_shake_amount += 0.1;
                                     }
-                                }
-                                CurrentItem::Gun => {
-                                    if gun_cooldown_animation <= 0.0 {
-                                        *world
-                                            .get::<&mut Handle<Material>>(player_item_art)
-                                            .unwrap() = pointed_gun.clone();
-                                    } else {
-                                        *world
-                                            .get::<&mut Handle<Material>>(player_item_art)
-                                            .unwrap() = gun_fire.clone();
+                                    SimEvent::Spit => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &spit_sounds, 0.8..1.2);
                                     }
-
-                                    if input.pointer_button_down(PointerButton::Primary) {
-                                        
-                                        if bullet_count > 0 {
-                                            bullet_count -= 1;
-                                            let blast_radius = 6.0;
-                                            grid_world.for_tile_in_radius(
-                                                pointer_position_new.xy(),
-                                                blast_radius,
-                                                |_, r, t| {
-                                                    let how_close_to_center =
-                                                        1.0 - (r / blast_radius);
-                                                    let scale = random.f32() * how_close_to_center;
-
-                                                    if scale > 0.1 {
-                                                        t.height = t
-                                                            .height
-                                                            .saturating_sub((150.0 * scale) as _);
-                                                        t.material = TileMaterial::Earth;
-                                                    }
-
-                                                    t.kill_flag = true;
-                                                    true
-                                                },
-                                            );
-
-
-                                            {
-                                                let sounds = resources.get::<AssetStore<Sound>>();
-                                                let sound = sounds.get(random.select_from_slice(&gun_sounds));
-                                                audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.8..1.2));
-                                            }
-
-                                            screen_shake_amount += 3.0;
-                                            gun_cooldown_animation = gun_cooldown_animation_reset;
-
-                                            let distance_to_left_foot = (pointer_position.xy()
-                                                - Vec2::new(40.0, 37.0))
-                                            .abs();
-                                            let distance_to_right_foot = (pointer_position.xy()
-                                                - Vec2::new(60.5, 36.0))
-                                            .abs();
-
-                                            if (distance_to_left_foot.x < 5.0
-                                                && distance_to_left_foot.y < 13.0)
-                                                || (distance_to_right_foot.x < 5.0
-                                                    && distance_to_right_foot.y < 13.0)
-                                            {
-                                                {
-                                                    let sounds = resources.get::<AssetStore<Sound>>();
-                                                    let sound = sounds.get(random.select_from_slice(&squish_sounds));
-                                                    audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.3..0.4));                
-                                                }
-                                                println!("P: {:?}", pointer_position.xy());
-                                                // SHOT FOOT
-                                                player_hurt_effect += 4.0;
-                                                screen_shake_amount += 40.0;
-                                                player_health -= 10;
-                                            }
-                                        } else {
-                                            // TODO: Play click sound.
-                                            {
-                                                let sounds = resources.get::<AssetStore<Sound>>();
-                                                let sound = sounds.get(random.select_from_slice(&out_of_bullets_sounds));
-                                                audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.9..1.1));
-                                            }
-                                        }
+                                    SimEvent::GunFired => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &gun_sounds, 0.8..1.2);
+                                        screen_shake_amount += 3.0;
+                                        gun_cooldown_animation = gun_cooldown_animation_reset;
                                     }
-                                    pointer_max_speed = None;
-
-                                    if !sandbox_mode {
-                                        pointer_position.y = pointer_position.y.min(max_hand_reach);
+                                    SimEvent::OutOfBullets => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &out_of_bullets_sounds, 0.9..1.1);
                                     }
-                                }
-                                CurrentItem::Toothpaste => {
-                                    *world
-                                        .get::<&mut Handle<Material>>(player_item_art)
-                                        .unwrap() = toothpaste_hand.clone();
-                                    if !sandbox_mode {
-                                        pointer_position.y = pointer_position.y.min(max_hand_reach);
+                                    SimEvent::FootShot => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &squish_sounds, 0.3..0.4);
+                                        player_hurt_effect += 4.0;
+                                        screen_shake_amount += 40.0;
                                     }
-
-                                    let max_squeeze = 1.0;
-
-                                    if input.pointer_button_down(PointerButton::Primary) {
-                                        toothpaste_squeeze = max_squeeze;
+                                    SimEvent::DebugHurt => {
+                                        screen_shake_amount += 3.0;
                                     }
-
-                                    if input.pointer_button(PointerButton::Primary) {
-                                        let radius = 2.0 * (toothpaste_squeeze / max_squeeze);
-                                        grid_world.for_tile_in_radius(
-                                            pointer_position.xy(),
-                                            radius,
-                                            |_, r, t| {
-                                                match t.material {
-                                                    TileMaterial::ToothPaste1 | TileMaterial::ToothPaste2 | TileMaterial::ToothPaste3 => {
-                                                        return false;
-                                                    }
-                                                    _ => {}
-                                                }
-
-                                                if toothpaste_tile_count <= 0 {
-                                                    toothpaste_tile_count = 0;
-                                                    return false;
-                                                }
-                                                toothpaste_tile_count -= 1;
-
-                                                t.height = 255;
-                                                t.height = (((1.0 - (r / radius)) * 200.0) as u8)
-                                                    .max(t.height);
-
-                                                match random.range_u32(0..3) {
-                                                    0 => {
-                                                        t.material = TileMaterial::ToothPaste1;
-                                                    }
-                                                    1 => {
-                                                        t.material = TileMaterial::ToothPaste2;
-                                                    }
-                                                    2 => {
-                                                        t.material = TileMaterial::ToothPaste3;
-                                                    }
-                                                    _ => t.material = TileMaterial::ToothPaste1,
-                                                }
-                                                true
-                                            },
-                                        );
-
-                                        //  toothpaste_squeeze -= 0.02;
-                                        toothpaste_squeeze = toothpaste_squeeze.max(0.0);
-                                        pointer_max_speed = Some(10.0);
-                                    } else {
-                                        pointer_max_speed = None;
+                                    SimEvent::Defeated => {
+                                        play_sound(&mut audio_manager, &sounds, &mut random, &defeat_sounds, 0.8..1.2);
                                     }
-                                }
-                                CurrentItem::Finger => {
-                                    // Max distance a hand can reach
-                                    if !sandbox_mode {
-                                        pointer_position.y = pointer_position.y.min(max_hand_reach);
+                                    SimEvent::Restarted => {
+                                        intro_interpolate = 0.0;
                                     }
-                                    if input.pointer_button(PointerButton::Primary) {
-                                        *world
-                                            .get::<&mut Handle<Material>>(player_item_art)
-                                            .unwrap() = pointed_finger_material.clone();
-
-                                        let slide_scale =
-                                            (pointer_position - pointer_last_position).length();
-
-                                        //println!("XY: {:?}", pointer_position.xy());
-
-                                        grid_world.for_tile_in_radius(
-                                            pointer_position.xy(),
-                                            3.0,
-                                            |_, _, t| {
-                                                if slide_scale > 0.2 {
-                                                    let amount = 14.0 * (slide_scale / 3.0);
-                                                    t.height = t.height.saturating_sub(amount as _);
-                                                }
-
-                                                if t.height < 30 {
-                                                    t.material = TileMaterial::Earth;
-                                                }
-
-                                                // t.material = TileMaterial::AntBody;
-
-                                                if input.pointer_button_down(PointerButton::Primary)
-                                                {
-                                                    t.kill_flag = true;
-                                                }
-                                                true
-                                            },
-                                        );
-                                    } else {
-                                        *world
-                                            .get::<&mut Handle<Material>>(player_item_art)
-                                            .unwrap() = curled_finger_material.clone();
+                                    SimEvent::SandboxStarted => {
+                                        intro_interpolate = 100.0;
                                     }
-                                }
-                            }
-                        }
-
-                        // Update user input.
-                        world.get::<&mut Transform>(player_item).unwrap().position =
-                            pointer_position;
-
-
-                        // Move spitballs
-                        for (e, (transform, spitball)) in
-                            world.query::<(&mut Transform, &mut SpitBall)>().iter()
-                        {
-                            let dir = (spitball.target - transform.position.xy()).normalized();
-
-                            let spitball_speed = 0.8;
-                            transform.position += (dir * spitball_speed).extend(0.0);
-                            if (transform.position.xy() - spitball.target).length() < 1.0 {
-                                to_despawn.push(e);
-                                println!("REACHED DESTINATION!");
-                                grid_world.for_tile_in_radius(transform.position.xy(), 3.0, |tile_p, _, tile| {
-                                    if let Some(material) = spitball.material.pop() {
-                                        tile.height += 100;
-                                        tile.material = material;
+                                    SimEvent::WaveText(lines) => {
+                                        ui_transparency_animation = 1.0;
+                                        ui_state.ui_lines = lines;
                                     }
-                                    tile.kill_flag = true;
-                                    true
-                                });
-                            }
-                        }
-
-                        // Ant Behavior
-                        for (e, (transform, ant)) in
-                            world.query::<(&mut Transform, &mut Ant)>().iter()
-                        {
-                            let p = transform.position;
-                            let mut height_here = 0;
-
-                            let mut will_die = false;
-
-                            if let Some(tile_here) = grid_world.get_tile(p.x, p.y) {
-                                if !ant.is_spider {
-                                    if ant.return_mode {
-                                        tile_here.pheremone_level -= 1;
-                                        tile_here.height -= 1;
-                                        tile_here.times_changed += 1;
-                                    } else {
-                                        tile_here.pheremone_level += 1;
-                                        tile_here.times_changed += 1;
+                                    SimEvent::WaveSpawned => {
+                                        ui_transparency_animation = 1.0;
                                     }
-                                    if tile_here.height > 150 && random.f32() > 0.5 {
-                                        tile_here.height = tile_here.height.saturating_sub(1);
+                                    SimEvent::BonusText(text, duration) => {
+                                        ui_state.bonus_text = text;
+                                        ui_state.display_bonus_text = duration;
                                     }
-                                    height_here = tile_here.height;
-
-                                    // Consider dropping material
-                                    if ant.return_mode
-                                        && p.y > world_size.y - 20.0
-                                        && random.f32() > 0.95
-                                    {
-                                        if tile_here.material == TileMaterial::Earth {
-                                            ant.return_mode = false;
-
-                                            if let Some(carrying) = ant.carrying.take() {
-                                                tile_here.material = carrying;
-                                                //tile_here.height += 10;
-                                            }
-                                        }
+                                    SimEvent::BonusTextDuration(duration) => {
+                                        ui_state.display_bonus_text = duration;
                                     }
-                                
-
-                                    // Consider picking up things.
-                                    if p.y < 180.0 && ant.carrying.is_none() {
-                                        match tile_here.material {
-                                            TileMaterial::AntBody | TileMaterial::Nacho => {
-                                                ant.return_mode = true;
-                                                ant.carrying = Some(tile_here.material);
-                                                tile_here.material = TileMaterial::Earth;
+                                    SimEvent::SaveRequested => {
+                                        match save::save_to_file(&simulation, &save_path) {
+                                            Ok(()) => info!("SAVED TO {:?}", save_path),
+                                            Err(e) => {
+                                                warn!("Could not save to {:?}: {}", save_path, e)
                                             }
-                                            _ => {}
                                         }
                                     }
-
-                                    // Toothpaste hurts!
-                                    match tile_here.material {
-                                        TileMaterial::ToothPaste1
-                                        | TileMaterial::ToothPaste2
-                                        | TileMaterial::ToothPaste3 => {
-                                            // ant.health = ant.health.saturating_sub(2);
-                                        }
-                                        _ => {}
-                                    }
-                                }
-
-
-                                if tile_here.kill_flag || (!ant.is_spider && tile_here.kill_ants_only) {
-                                    will_die = true;
-
-                                    // Killed violently, scatter guts.
-                                    grid_world.for_tile_in_radius(p.xy(), 2.0, |_tile_p, _, t| {
-                                        if random.f32() > 0.8 {
-                                            t.material = TileMaterial::AntGuts;
-                                            t.height += 5;
-                                        }
-                                        false
-                                    });
-
-                                    let sounds = resources.get::<AssetStore<Sound>>();
-                                    let sound = sounds.get(random.select_from_slice(&squish_sounds));
-                                    audio_manager.play_one_shot_with_speed(sound, random.range_f32(1.0..2.0));
-                                    
-                                }
-
-
-                                if ant.is_spider {
-                                    grid_world.for_tile_in_radius(p.xy(), 10.0, |_tile_p, _, t| {
-                                        t.kill_ants_only = true;
-                                        true
-                                    });
-                                }
-                            }
-
-                            // The ant moves towards the tile with the lowest score.
-                            let mut lowest_score = i32::MAX;
-
-                            let mut lowest_score_tile = None;
-                            let mut height_speed_multiplier = 1.0;
-
-                            grid_world.for_tile_in_radius(p.xy(), 4.0, |tile_p, _, t| {
-                                let alignment = (p.xy() - tile_p).dot(ant.dir);
-                                let alignment_score_adjust = if alignment > 0.0 { 300 } else { 0 };
-
-                                let height_diff = t.height as f32 - height_here as f32;
-
-                                let height_score_adjust = if height_diff < 50.0 {
-                                    // Next tile is lower
-                                    (height_diff.abs() / 255.0) * 100.0
-                                } else {
-                                    // Next tile is greater
-                                    (height_diff.abs() / 255.0) * 1000.0
-                                };
-
-                                let pheremone_score = if ant.return_mode {
-                                    -(t.pheremone_level as i32)
-                                } else {
-                                    t.pheremone_level as i32
-                                };
-
-                                let mut tile_score = pheremone_score
-                                    + height_score_adjust as i32
-                                    + alignment_score_adjust;
-
-                                // Random score adjust
-                                // I believe this biases towards right tiles.
-                                // tile_score += random.range_i32(0..4);
-
-                                if tile_p.y > world_size.y - 5.0 {
-                                    tile_score = i32::MAX;
-                                }
-
-                                if ant.is_spider {
-                                    if (tile_p.xy() - ant.home).length() > 50.0 {
-                                        tile_score = (((tile_p.xy() - ant.home).length() / 30.0) * 100.0) as i32;
-                                    } else {
-                                        tile_score = -(t.times_changed as i32);
-                                    }
-
-                                }
-
-                                let height_veto = t.height > 230 && random.f32() > 0.01;
-
-                                if !height_veto && tile_score < lowest_score {
-                                    lowest_score = tile_score;
-                                    lowest_score_tile = Some(tile_p);
-
-                                    height_speed_multiplier = (1.0
-                                        - (height_score_adjust / 20.0).min(1.0))
-                                    .clamp(0.5, 1.0);
-                                }
-                                false
-                            });
-
-                         
-
-                            // println!("LOWEST SCORE TILE: {:?}", lowest_score_tile);
-                            // println!("ANT P: {:?}", p.xy());
-                            // println!("SCORE: {:?}", lowest_score);
-
-                            if let Some(lowest_score_tile) = lowest_score_tile {
-                                let tile_p = p.xy().as_i32().as_f32();
-
-                                let unnormalized_dir = lowest_score_tile - tile_p.xy();
-                                let len = unnormalized_dir.length();
-
-                                if len != 0.0 {
-                                    let new_dir = unnormalized_dir / len;
-                                    //ant.target_score = lowest_pheremone_v;
-                                    // println!("P: {:?}", p);
-                                    ant.dir = new_dir;
-                                    // ant.dir = -Vec2::Y;
-                                    // println!("FORWARD: {:?}", transform.forward());
-                                    let angle = ant.dir.y.atan2(ant.dir.x);
-                                    transform.rotation = Quaternion::from_angle_axis(
-                                        angle - std::f32::consts::PI / 2.0,
-                                        Vec3::Z,
-                                    );
-                                } else {
-                                    ant.dir = Vec2::ZERO;
                                 }
-                            } else {
-                                ant.dir = Vec2::ZERO;
                             }
 
-                            // can't work due to borrowing rules
-                            /*
-                            if ant.is_spider {
-                                let mut closest = None;
-                                let mut closest_d = f32::MAX;
-                                for (e, (a, t)) in world.query::<(&Ant,&Transform)>().iter() {
-                                    if !a.is_spider {
-                                        let length_squared = (t.position.xy() - p.xy()).length_squared();
-                                        if length_squared < 50.0 * 50.0 && length_squared < closest_d {
-                                            closest_d = length_squared;
-                                            closest = Some(t.position.xy());
-                                        }
-                                    }
-                                }
-
-                                if let Some(closest) = closest {
-                                    ant.dir = (closest - p.xy()).normalized();
+                            let straw_sound_playing = simulation.hotbar.selected
+                                == CurrentItem::Straw
+                                && simulation.straw_sucking
+                                && simulation.straw_contents.len() < STRAW_CAPACITY;
+                            if straw_sound_playing {
+                                if current_straw_sound.is_none() {
+                                    let sound = sounds.get(&suck_sound);
+
+                                    current_straw_sound = Some(audio_manager
+                                        .play_one_shot_oddio(oddio::MonoToStereo::new(
+                                            oddio::Cycle::new(sound.frames.clone())
+                                    )));
                                 }
+                            } else if let Some(mut current_straw_sound) = current_straw_sound.take() {
+                                current_straw_sound.control::<oddio::Stop<_>, _>().stop();
                             }
-                            */
-
-                            //   println!("FINAL DIR: {:?}", ant.dir);
-
-                            let mut speed_multiplier = 1.0;
-                            if ant.return_mode {
-                                speed_multiplier *= 1.5
-                            }
-
+                        }
 
-                            if transform.position.y > world_size.y - 1.0 {
-                                ant.dir = -Vec2::Y;
-                            }
+                        // Intro Sequence Stuff
 
-                            if ant.health < 100 {
-                                if random.f32() > 0.8 {
-                                    ant.health = ant.health.saturating_sub(1)
-                                }
-                            }
+                        if simulation.started_once && !simulation.sandbox_mode {
+                            intro_interpolate += 0.002;
 
-                            if ant.health == 0 {
-                                will_die = true;
+                            if intro_interpolate < 1.0 {
+                                let mut camera: RefMut<'_, Transform> =
+                                    world.get::<&mut Transform>(camera).unwrap();
+                                let curve = koi3::animation_curves::smooth_step(intro_interpolate);
+                                camera.position.y = camera_max + (camera_min - camera_max) * curve;
                             }
+                            resources.get::<UIState>().transparency =
+                                intro_interpolate.min(1.0 - ui_transparency_animation);
+                        }
 
-                            if !sandbox_mode && !victory
-                                && player_health > 0
-                                && transform.position.y > 15.0
-                                && (transform.position.xy() - Vec2::new(world_size.x / 2.0, 15.0))
-                                    .length()
-                                    < 10.0
-                            {
-                                // Hurt player
-                                player_health -= 1;
-                                screen_shake_amount += 1.0;
-                                player_hurt_effect += 1.0;
-
-                                let sounds = resources.get::<AssetStore<Sound>>();
-                                let sound = sounds.get(random.select_from_slice(&squish_sounds));
-                                audio_manager.play_one_shot_with_speed(sound, random.range_f32(0.3..0.5));
-
-                                println!("ANT GOT PASSED: {:?}", player_health);
-
-                                to_despawn.push(e);
-                            }
+                        if items::item(simulation.hotbar.selected).aims() {
+                            world
+                                .get::<&mut Transform>(reticule_entity)
+                                .unwrap()
+                                .position = tick_input.pointer.xy().extend(-10.0);
 
-                            if will_die {
-                                to_despawn.push(e);
+                            *world.get::<&mut RenderFlags>(reticule_entity).unwrap() =
+                                RenderFlags::DEFAULT;
+                        } else {
+                            *world.get::<&mut RenderFlags>(reticule_entity).unwrap() =
+                                RenderFlags::NONE;
+                        }
 
-                                grid_world.for_tile_in_radius(p.xy(), 1.0, |_tile_p, _, t| {
-                                    if random.f32() > 0.4 {
-                                        t.material = TileMaterial::AntBody;
-                                        t.height = 155;
-                                    }
-                                    false
+                        if simulation.player_health > 0 || simulation.sandbox_mode {
+                            let item_art = items::item(simulation.hotbar.selected)
+                                .hand_art()
+                                .map(|art| {
+                                    let path = match (art.fired, art.pressed) {
+                                        (Some(fired), _) if gun_cooldown_animation > 0.0 => fired,
+                                        (_, Some(pressed)) if tick_input.pointer_held => pressed,
+                                        _ => art.idle,
+                                    };
+                                    hand_art[path].clone()
                                 });
-
-                                // Ant death pheromones to attract more ants.
-                                let ant_pheremone_radius = 10.0;
-                                grid_world.for_tile_in_radius(
-                                    p.xy(),
-                                    ant_pheremone_radius,
-                                    |_tile_p, radius, t| {
-                                        t.pheremone_level -=
-                                            (5.0 * (1.0 - (radius / ant_pheremone_radius))) as i16;
-                                        t.pheremone_level = t.pheremone_level.max(0);
-                                        false
-                                    },
-                                );
-
-                                // Drop what it's carrying if it dies.
-                                let tile_here = grid_world.get_tile(p.x, p.y).unwrap();
-                                if let Some(carrying) = ant.carrying.take() {
-                                    tile_here.material = carrying;
-                                }
+                            if let Some(item_art) = item_art {
+                                *world.get::<&mut Handle<Material>>(player_item_art).unwrap() =
+                                    item_art;
                             }
-
-                            let health_speed_multiplier = ant.health as f32 / 255.0;
-
-
-                            if ant.is_spider {
-                                speed_multiplier = 5.0;;
-                            }
-
-                            transform.position += ant.dir.extend(0.0)
-                                * ant.speed
-                                * speed_multiplier
-                                * height_speed_multiplier
-                                * health_speed_multiplier;
                         }
 
-                    
+                        // Update user input.
+                        world.get::<&mut Transform>(player_item).unwrap().position =
+                            simulation.pointer_position;
 
-                        // println!("P: {:?}", pointer_position);
+                        sync_ants(world, &mut ant_entities, &simulation.ants, &enemy_materials);
+                        sync_spitballs(world, &mut spitball_entities, &simulation.spitballs);
+                        sync_direction_lines(
+                            world,
+                            &mut direction_line_entities,
+                            if show_ant_directions {
+                                &simulation.ants
+                            } else {
+                                &[]
+                            },
+                            &direction_line_material,
+                        );
+                        sync_defend_targets(
+                            world,
+                            &mut defend_target_entities,
+                            &mut synced_defend_targets,
+                            &simulation.defend_targets,
+                            &foot_material,
+                            &sandwich_material,
+                        );
+                        let (spawn_points, targets) = match edit_path {
+                            Some(_) => (
+                                simulation.spawn_points.clone(),
+                                simulation.defend_targets.iter().map(|t| t.position).collect(),
+                            ),
+                            None => (Vec::new(), Vec::new()),
+                        };
+                        sync_markers(
+                            world,
+                            &mut spawn_point_entities,
+                            &spawn_points,
+                            &spawn_point_material,
+                        );
+                        sync_markers(
+                            world,
+                            &mut target_marker_entities,
+                            &targets,
+                            &target_marker_material,
+                        );
+                        slider_view.sync(world, &simulation.slider, |option| match option {
+                            SliderOption::Gun => gun_slider_material.clone(),
+                            SliderOption::Bullets => bullets_slider_material.clone(),
+                            SliderOption::Nachos => nacho_slider_material.clone(),
+                            SliderOption::Toothpaste => toothpaste_slider_color.clone(),
+                        });
 
                         // Scroll the camera if needed.
 
-                        if !victory && player_health > 0 && intro_interpolate > 1.0 {
+                        if !simulation.victory && simulation.player_health > 0 && intro_interpolate > 1.0 {
                             let window = resources.get::<kapp::Window>();
                             let (_window_width, window_height) = window.size();
                             let window_position =
@@ -2310,21 +1126,7 @@ fn main() {
                                 camera.position.y -= camera_move_rate * multiplier * multiplier;
                             }
 
-                            //println!("CAMERA POSITION Y: {:?}", camera.position.y);
-                            camera.position.y = camera.position.y.clamp(camera_min, 160.0);
-                        }
-
-                        grid_world.update_tiles();
-                        pointer_last_position = pointer_position;
-
-                        for e in to_despawn {
-                            let _ = world.despawn(e);
-                        }
-
-                        if input.pointer_button(PointerButton::Primary) {
-                            pointer_held_len += 1;
-                        } else {
-                            pointer_held_len = 0;
+                            camera.position.y = camera.position.y.clamp(camera_min, camera_top);
                         }
                     }
                     _ => {}
@@ -2333,317 +1135,447 @@ fn main() {
         });
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
-enum CurrentItem {
-    Finger,
-    Straw,
-    Nacho,
-    Toothpaste,
-    Gun,
-    Spider,
-    Inspector,
+/// The entities that display the rummage slider.
+struct SliderView {
+    entities: Vec<Entity>,
+    line_entity: Entity,
+    targets: Vec<((f32, f32), Entity)>,
 }
 
-struct GridWorld {
-    width: usize,
-    height: usize,
-    tiles: Vec<Tile>,
-    texture_scratch: Vec<[f32; 4]>,
-    tiles_to_update: VecDeque<usize>,
-}
-#[derive(Clone, Copy)]
-struct Tile {
-    height: u8,
-    material: TileMaterial,
-    /// How much ants have explored here.
-    pheremone_level: i16,
-    kill_flag: bool,
-    kill_ants_only: bool,
-    times_changed: u32,
-}
+impl SliderView {
+    fn new(world: &mut World, parent: Entity, base_material: Handle<Material>) -> Self {
+        let mut entities = Vec::new();
+        let base_entity = world.spawn((
+            Transform::new()
+                .with_position(Vec3::new(0.0, -20.0, -30.0))
+                .with_scale(Vec3::new(50.0, 5.0, 1.0)),
+            Mesh::VERTICAL_QUAD,
+            RenderFlags::NONE,
+            base_material,
+        ));
+        let _ = world.set_parent(parent, base_entity);
+        entities.push(base_entity);
+
+        let line_entity = world.spawn((
+            Transform::new()
+                .with_position(Vec3::new(0.0, 0.0, -2.0))
+                .with_scale(Vec3::new(0.01, 1.4, 1.0)),
+            Mesh::VERTICAL_QUAD,
+            Material::UNLIT,
+            RenderFlags::NONE,
+        ));
+        let _ = world.set_parent(base_entity, line_entity);
+        entities.push(line_entity);
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-
-enum TileMaterial {
-    Earth,
-    Grass,
-    AntGuts,
-    AntBody,
-    ToothPaste1,
-    ToothPaste2,
-    ToothPaste3,
-    Nacho,
-    Debug,
-}
-
-impl GridWorld {
-    pub fn new(width: usize, height: usize) -> Self {
         Self {
-            width,
-            height,
-            tiles: vec![
-                Tile {
-                    height: 128,
-                    material: TileMaterial::Earth,
-                    pheremone_level: 0,
-                    kill_flag: false,
-                    kill_ants_only: false,
-                    times_changed: 0
-                };
-                width * height
-            ],
-            texture_scratch: Vec::new(),
-            tiles_to_update: VecDeque::new(),
+            entities,
+            line_entity,
+            targets: Vec::new(),
         }
     }
 
-    pub fn update_tiles(&mut self) {
-        while let Some(i) = self.tiles_to_update.pop_back() {
-            let tile = &mut self.tiles[i];
-            tile.kill_flag = false;
-            tile.kill_ants_only = false;
+    fn sync(
+        &mut self,
+        world: &mut World,
+        slider: &Slider,
+        material_for: impl Fn(SliderOption) -> Handle<Material>,
+    ) {
+        let render_flags = if slider.visible {
+            RenderFlags::DEFAULT
+        } else {
+            RenderFlags::NONE
+        };
+
+        let targets_changed = self.targets.len() != slider.targets.len()
+            || self
+                .targets
+                .iter()
+                .zip(slider.targets.iter())
+                .any(|(a, b)| a.0 != b.0);
+
+        if targets_changed {
+            for (_, e) in self.targets.drain(..) {
+                let _ = world.despawn(e);
+            }
+
+            let parent = self.entities[0];
+            for ((start, end), option) in slider.targets.iter() {
+                let len = end - start;
+                let e = world.spawn((
+                    Transform::new()
+                        .with_position(Vec3::new(-0.5 + start + len / 2.0, -0.0, -1.0))
+                        .with_scale(Vec3::new(len, 1.0, 1.0)),
+                    Mesh::VERTICAL_QUAD,
+                    render_flags,
+                    material_for(*option),
+                ));
+                let _ = world.set_parent(parent, e);
+                self.targets.push(((*start, *end), e));
+            }
         }
-    }
 
-    pub fn reset_pheromones(&mut self) {
-        let mut r = Random::new();
-        for (i, t) in self.tiles.iter_mut().enumerate() {
-            let x = i / self.height;
-            let y = i % self.height;
+        for entity in self.entities.iter() {
+            *world.get::<&mut RenderFlags>(*entity).unwrap() = render_flags;
+        }
 
-            let base_level = 1000;
-            let height_pheremone_offset = (y as f32 / self.height as f32) * 200.0;
-            t.pheremone_level =
-                height_pheremone_offset as i16 + r.range_u32(0..20) as i16 + base_level;
+        for (_, e) in self.targets.iter() {
+            *world.get::<&mut RenderFlags>(*e).unwrap() = render_flags;
+        }
 
-            let distance_to_feet =
-                (Vec2::new(x as f32, y as f32) - Vec2::new(self.width as f32 / 2.0, 13.0)).length();
+        world
+            .get::<&mut Transform>(self.line_entity)
+            .unwrap()
+            .position
+            .x = -0.5 + slider.line_position;
+    }
+}
 
-            t.pheremone_level += distance_to_feet as i16 * 2;
+/// Keep one sprite per simulated ant.
+fn sync_ants(
+    world: &mut World,
+    entities: &mut Vec<Entity>,
+    ants: &[Ant],
+    enemy_materials: &[Handle<Material>],
+) {
+    while entities.len() > ants.len() {
+        let _ = world.despawn(entities.pop().unwrap());
+    }
+    while entities.len() < ants.len() {
+        entities.push(world.spawn((
+            Transform::new().with_scale(Vec3::fill(2.0)),
+            Mesh::VERTICAL_QUAD,
+            //Material::UNLIT,
+            enemy_materials[0].clone(),
+        )));
+    }
 
-            if y > self.height - 3 {
-                t.pheremone_level = i16::MAX;
-            }
-            t.times_changed = 0;
+    for (ant, entity) in ants.iter().zip(entities.iter()) {
+        {
+            let mut transform = world.get::<&mut Transform>(*entity).unwrap();
+            transform.position = ant.position;
+            transform.rotation = Quaternion::from_angle_axis(
+                ant.facing - std::f32::consts::PI / 2.0,
+                Vec3::Z,
+            );
         }
+
+        *world.get::<&mut Handle<Material>>(*entity).unwrap() = enemy_materials[ant.kind.0].clone();
     }
+}
 
-    pub fn neutral_pheremones(&mut self) {
-        for (_, t) in self.tiles.iter_mut().enumerate() {
-            t.pheremone_level = 0;
-            t.times_changed = 0;
-        }
+/// Draws a line from each ant along the direction it's moving in.
+fn sync_direction_lines(
+    world: &mut World,
+    entities: &mut Vec<Entity>,
+    ants: &[Ant],
+    material: &Handle<Material>,
+) {
+    while entities.len() > ants.len() {
+        let _ = world.despawn(entities.pop().unwrap());
+    }
+    while entities.len() < ants.len() {
+        entities.push(world.spawn((
+            Transform::new(),
+            Mesh::VERTICAL_QUAD,
+            material.clone(),
+        )));
+    }
+
+    for (ant, entity) in ants.iter().zip(entities.iter()) {
+        // Faster ants get longer lines.
+        let length = 2.0 + ant.speed * 20.0;
+        let mut transform = world.get::<&mut Transform>(*entity).unwrap();
+        transform.position = ant.position + (ant.dir * (length / 2.0)).extend(0.1);
+        transform.scale = Vec3::new(0.2, length, 1.0);
+        transform.rotation = Quaternion::from_angle_axis(
+            ant.dir.y.atan2(ant.dir.x) - std::f32::consts::PI / 2.0,
+            Vec3::Z,
+        );
+    }
+}
+
+/// Where a target's sprites go relative to it, how big they are and whether they're mirrored.
+fn defend_target_sprites(kind: DefendTargetKind) -> &'static [(Vec2, f32, bool)] {
+    const FEET: [(Vec2, f32, bool); 2] = [
+        (Vec2::new(-5.0, 15.0), 50.0, false),
+        (Vec2::new(15.0, 15.0), 50.0, true),
+    ];
+    match kind {
+        DefendTargetKind::Feet => &FEET,
+        DefendTargetKind::Sandwich => &[(Vec2::ZERO, 12.0, false)],
     }
+}
 
-    pub fn reverse_deterent_pheromones(&mut self) {
-        let mut r = Random::new();
-        for (i, t) in self.tiles.iter_mut().enumerate() {
-            let y = i % self.height;
+/// Targets rarely change, so their sprites are only rebuilt when they do.
+fn sync_defend_targets(
+    world: &mut World,
+    entities: &mut Vec<Entity>,
+    synced: &mut Vec<DefendTarget>,
+    targets: &[DefendTarget],
+    foot_material: &Handle<Material>,
+    sandwich_material: &Handle<Material>,
+) {
+    if synced.as_slice() == targets {
+        return;
+    }
+    for entity in entities.drain(..) {
+        let _ = world.despawn(entity);
+    }
 
-            if y < self.height - 60 {
-                t.pheremone_level = i16::MAX;
+    for target in targets {
+        let material = match target.kind {
+            DefendTargetKind::Feet => foot_material,
+            DefendTargetKind::Sandwich => sandwich_material,
+        };
+        for (offset, size, mirrored) in defend_target_sprites(target.kind) {
+            let mut transform = Transform::new()
+                .with_position((target.position + *offset).extend(-1.0))
+                .with_scale(Vec3::fill(*size));
+            if *mirrored {
+                transform = transform
+                    .with_rotation(Quaternion::from_angle_axis(std::f32::consts::PI, Vec3::Y));
             }
-            t.times_changed = 0;
+            entities.push(world.spawn((transform, Mesh::VERTICAL_QUAD, material.clone())));
         }
     }
+    *synced = targets.to_vec();
+}
 
-    pub fn initial_terrain_setup(&mut self) {
-        let noise2d: clatter::Simplex2d = clatter::Simplex2d::new();
-
-        for (i, t) in self.tiles.iter_mut().enumerate() {
-            t.material = TileMaterial::Earth;
-            let x = i / self.height;
-            let y = i % self.height;
-
-            let scale = 30.0;
-            let sample2d =
-                (sample_with_octaves::<8>(&noise2d, 0.5, x as f32 / scale, y as f32 / scale) + 1.0)
-                    / 2.0;
-
-            let terrain_type = (sample_with_octaves::<4>(
-                &noise2d,
-                0.5,
-                x as f32 / scale + 3000.0,
-                y as f32 / scale + 7000.0,
-            ) + 1.0)
-                / 2.0;
-
-            if terrain_type > 0.8 {
-                t.material = TileMaterial::Grass;
-            }
+fn sync_markers(
+    world: &mut World,
+    entities: &mut Vec<Entity>,
+    positions: &[Vec2],
+    material: &Handle<Material>,
+) {
+    while entities.len() > positions.len() {
+        let _ = world.despawn(entities.pop().unwrap());
+    }
+    while entities.len() < positions.len() {
+        entities.push(world.spawn((
+            Transform::new().with_scale(Vec3::fill(3.0)),
+            Mesh::VERTICAL_CIRCLE,
+            material.clone(),
+        )));
+    }
 
-            let offset = 150.0;
-            let height = (sample2d * offset) + (255.0 - offset);
-            t.height = height as u8;
-            t.times_changed = 0;
-        }
+    for (position, entity) in positions.iter().zip(entities.iter()) {
+        world.get::<&mut Transform>(*entity).unwrap().position = position.extend(0.2);
+    }
+}
 
-        self.reset_pheromones();
+fn sync_spitballs(world: &mut World, entities: &mut Vec<Entity>, spitballs: &[SpitBall]) {
+    while entities.len() > spitballs.len() {
+        let _ = world.despawn(entities.pop().unwrap());
+    }
+    while entities.len() < spitballs.len() {
+        entities.push(world.spawn((
+            Transform::new().with_scale(Vec3::fill(2.0)),
+            Mesh::VERTICAL_CIRCLE,
+            Material::UNLIT,
+        )));
     }
 
-    pub fn get_tile(&mut self, x: f32, y: f32) -> Option<&mut Tile> {
-        if x < 0.0 || y < 0.0 || x > self.width as _ || y > self.height as _ {
-            return None;
-        }
-        let i = x as usize * self.height + y as usize;
-        self.tiles.get_mut(i)
+    for (spitball, entity) in spitballs.iter().zip(entities.iter()) {
+        world.get::<&mut Transform>(*entity).unwrap().position = spitball.position;
     }
+}
 
-    pub fn for_tile_in_radius(
-        &mut self,
-        center: Vec2,
-        radius: f32,
-        mut f: impl FnMut(Vec2, f32, &mut Tile) -> bool,
-    ) {
-        let min: Matrix<f32, 2, 1> = center - Vec2::fill(radius);
-        let max: Matrix<f32, 2, 1> = center + Vec2::fill(radius);
-
-        let min = min.as_usize();
-        let max = max
-            .as_usize()
-            .max(Vector::<_, 2>::new(self.width, self.height));
-
-        for x in min.x..max.x {
-            for y in min.y..max.y {
-                let t = Vec2::new(x as f32, y as f32);
-                let i = x * self.height + y;
-                let distance = (t - center).length();
-                if distance < radius {
-                    if let Some(tile) = self.tiles.get_mut(i) {
-                        if f(Vec2::new(x as f32, y as f32), distance, tile) {
-                            self.tiles_to_update.push_back(i);
-                        }
+fn play_sound(
+    audio_manager: &mut AudioManager,
+    sounds: &AssetStore<Sound>,
+    random: &mut Random,
+    options: &[Handle<Sound>],
+    speed: std::ops::Range<f32>,
+) {
+    let sound = sounds.get(random.select_from_slice(options));
+    audio_manager.play_one_shot_with_speed(sound, random.range_f32(speed));
+}
+
+struct Args {
+    seed: Option<u64>,
+    /// Write every tick's input to this file.
+    record: Option<std::path::PathBuf>,
+    /// Play back the input from this file instead of live input.
+    replay: Option<std::path::PathBuf>,
+    /// Play the replay without opening a window and print how it ended.
+    headless: bool,
+    /// Where the campaign's waves are loaded from.
+    waves: Option<std::path::PathBuf>,
+    /// Where F5 saves to and F9 loads from.
+    save: Option<std::path::PathBuf>,
+    /// Start from this save instead of a new game.
+    load: Option<std::path::PathBuf>,
+    /// A level file for the waves that don't pick their own.
+    level: Option<String>,
+    /// Open the level editor on this file. It's created when saved if it isn't there.
+    edit: Option<std::path::PathBuf>,
+    /// The size of a new world or level, like `90x200`.
+    world_size: Option<Vec2>,
+    /// Let materials slump, spread and settle.
+    tile_physics: bool,
+    /// Steer the ants all at once, on several threads with the `parallel` feature.
+    /// Recordings have to be played back with it too.
+    parallel_ants: bool,
+    /// With --headless, save and load at this tick of the replay
+    /// and check that both copies end up the same.
+    save_round_trip: Option<usize>,
+    /// Turn on the cheat keys. Always on when built with the `dev` feature.
+    dev: bool,
+    /// The least important messages that are printed: error, warn, info or debug.
+    log_level: Option<log::Level>,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut parsed = Args {
+            seed: None,
+            record: None,
+            replay: None,
+            headless: false,
+            waves: None,
+            save: None,
+            load: None,
+            level: None,
+            edit: None,
+            world_size: None,
+            save_round_trip: None,
+            tile_physics: false,
+            parallel_ants: false,
+            dev: cfg!(feature = "dev"),
+            log_level: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    parsed.seed = args.next().and_then(|s| s.parse().ok());
+                    if parsed.seed.is_none() {
+                        exit_with_error("--seed expects a number");
+                    }
+                }
+                "--record" => parsed.record = Some(path_after(&mut args, &arg).into()),
+                "--replay" => parsed.replay = Some(path_after(&mut args, &arg).into()),
+                "--headless" => parsed.headless = true,
+                "--waves" => parsed.waves = Some(path_after(&mut args, &arg).into()),
+                "--tile-physics" => parsed.tile_physics = true,
+                "--parallel-ants" => parsed.parallel_ants = true,
+                "--save" => parsed.save = Some(path_after(&mut args, &arg).into()),
+                "--load" => parsed.load = Some(path_after(&mut args, &arg).into()),
+                "--level" => parsed.level = Some(path_after(&mut args, &arg)),
+                "--edit" => parsed.edit = Some(path_after(&mut args, &arg).into()),
+                "--world-size" => {
+                    parsed.world_size = args.next().as_deref().and_then(parse_world_size);
+                    if parsed.world_size.is_none() {
+                        exit_with_error(&format!(
+                            "--world-size expects a size of at least {}x{}, like 90x200",
+                            MIN_WORLD_SIZE.x, MIN_WORLD_SIZE.y
+                        ));
+                    }
+                }
+                "--save-round-trip" => {
+                    parsed.save_round_trip = args.next().and_then(|s| s.parse().ok());
+                    if parsed.save_round_trip.is_none() {
+                        exit_with_error("--save-round-trip expects a tick number");
+                    }
+                }
+                "--dev" => parsed.dev = true,
+                "--log-level" => {
+                    parsed.log_level = args.next().as_deref().and_then(log::Level::parse);
+                    if parsed.log_level.is_none() {
+                        exit_with_error("--log-level expects error, warn, info or debug");
                     }
                 }
+                _ => exit_with_error(&format!("Unknown argument: {}", arg)),
             }
         }
+        if parsed.save_round_trip.is_some() && !parsed.headless {
+            exit_with_error("--save-round-trip only works with --headless");
+        }
+        parsed
     }
 
-    pub fn update_texture(&mut self, world: &mut World, resources: &Resources, entity: Entity) {
-        let graphics = &mut resources.get::<Renderer>().raw_graphics_context;
-
-        if true
-        /*self.texture_scratch.len() >= 0*/
-        {
-            self.texture_scratch.clear();
-            self.texture_scratch.reserve(self.width * self.height);
-            for y in 0..self.height {
-                for x in 0..self.width {
-                    let t = self.tiles[x * self.height + (self.height - 1 - y)];
-                    // println!("V: {:?}", x * self.height + y);
-                    let c = match t.material {
-                        TileMaterial::Earth => Color::from_srgb_hex(0x7A613B, 1.0),
-                        TileMaterial::AntGuts => Color::YELLOW,
-                        TileMaterial::AntBody => Color::GREEN,
-                        TileMaterial::Debug => Color::MAGENTA,
-                        TileMaterial::Nacho => Color::ORANGE,
-                        TileMaterial::Grass => Color::from_srgb_hex(0xAAB55B, 1.0),
-                        TileMaterial::ToothPaste1 => Color::from_srgb_hex(0x2589D0, 1.0),
-                        TileMaterial::ToothPaste2 => Color::from_srgb_hex(0x2589D0, 1.0),
-                        TileMaterial::ToothPaste3 => Color::from_srgb_hex(0xE98EC5, 1.0),
-                    };
-
-                    let c = Color::interpolate(
-                        Color::from_srgb_hex(0x2A1605, 1.0),
-                        c,
-                        (t.height as f32 / 255.0).clamp(0.4, 1.0),
-                    )
-                    .to_srgb();
-
-                    self.texture_scratch.push([c[0], c[1], c[2], c[3]]);
-
-                    // data.push([
-                    //     x as f32 / self.width as f32,
-                    //     0.0, //y as f32 / self.height as f32,
-                    //     0.0,
-                    //     1.0,
-                    // ]);
-                }
-            }
+    fn ant_update(&self) -> AntUpdate {
+        if self.parallel_ants {
+            AntUpdate::Parallel
+        } else {
+            AntUpdate::Serial
         }
+    }
+}
 
-        /*
-        let data: Vec<_> = self
-            .tiles
-            .iter()
-            .enumerate()
-            .map(|(i, t)| {
-                let c = match t.material {
-                    TileMaterial::Earth => Color::interpolate(
-                        Color::from_srgb_hex(0x2A1605, 1.0),
-                        Color::from_srgb_hex(0x7A613B, 1.0),
-                        (t.height as f32 / 200.0).clamp(0.4, 1.0),
-                    )
-                    .to_srgb(),
-                };
+/// For arguments that can't be used, so a typo doesn't quietly play with the defaults.
+fn exit_with_error(message: &str) -> ! {
+    error!("{}", message);
+    std::process::exit(1);
+}
 
-                [c[0], c[1], c[2], c[3]]
-            })
-            .collect();
-        */
-
-        let new_texture = graphics.new_texture_with_data(
-            self.width as _,
-            self.height as _,
-            1,
-            &self.texture_scratch,
-            TextureSettings {
-                minification_filter: FilterMode::Nearest,
-                magnification_filter: FilterMode::Nearest,
-                generate_mipmaps: false,
-                ..Default::default()
-            },
-        );
+/// The path that has to follow `flag`.
+fn path_after(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
+    args.next()
+        .unwrap_or_else(|| exit_with_error(&format!("{} expects a path", flag)))
+}
 
-        let texture = resources
-            .get::<AssetStore<Texture>>()
-            .add(Texture(new_texture));
+fn parse_world_size(size: &str) -> Option<Vec2> {
+    let (width, height) = size.split_once('x')?;
+    let width: u32 = width.parse().ok()?;
+    let height: u32 = height.parse().ok()?;
+    level::check_world_size(width as usize, height as usize)
+        .is_ok()
+        .then(|| Vec2::new(width as f32, height as f32))
+}
 
-        let material = resources.get::<AssetStore<Material>>().add(Material {
-            base_color_texture: Some(texture),
-            shader: Shader::UNLIT,
-            ..Default::default()
-        });
+/// Loads what new games are set up with from `assets` and the command line.
+fn simulation_config(args: &Args, wave_file: &WaveFile) -> SimulationConfig {
+    let waves = with_default_level(wave_file.load_or_default(), &args.level);
+    let levels = level::load_wave_levels(&waves);
 
-        let _ = world.insert_one(entity, material);
+    let pheromone_config_path = "assets/pheromones.ron";
+    let pheromone_config = if std::path::Path::new(pheromone_config_path).exists() {
+        PheromoneConfig::load(pheromone_config_path).unwrap_or_else(|e| {
+            warn!("Could not load {:?}: {}", pheromone_config_path, e);
+            PheromoneConfig::default()
+        })
+    } else {
+        PheromoneConfig::default()
+    };
+
+    let materials_path = "assets/materials.ron";
+    let materials = if std::path::Path::new(materials_path).exists() {
+        MaterialRegistry::load(materials_path).unwrap_or_else(|e| {
+            warn!("Could not load {:?}: {}", materials_path, e);
+            MaterialRegistry::new()
+        })
+    } else {
+        MaterialRegistry::new()
+    };
+
+    SimulationConfig {
+        waves,
+        levels,
+        pheromone_config,
+        materials,
+        tile_physics: args.tile_physics,
+        ant_update: args.ant_update(),
     }
 }
 
-fn sample_with_octaves<const LANES: usize>(
-    noise: &clatter::Simplex2d,
-    persistence: f32,
-    x: f32,
-    y: f32,
-) -> f32
-where
-    std::simd::LaneCount<LANES>: std::simd::SupportedLaneCount,
-{
-    use std::simd::num::SimdFloat;
-
-    let mut frequency = 1.0;
-    let mut amplitude = 1.0;
-    let mut max_value = 0.0;
-
-    let mut amplitudes: [f32; LANES] = [0.0; LANES];
-    let mut frequencies: [f32; LANES] = [0.0; LANES];
-
-    for i in 0..LANES {
-        amplitudes[i] = amplitude;
-        frequencies[i] = frequency;
-
-        max_value += amplitude;
-        amplitude *= persistence;
-        frequency *= 2.0;
+/// With `--level`, the waves that don't pick a level of their own use that one.
+fn with_default_level(mut waves: Vec<Wave>, level: &Option<String>) -> Vec<Wave> {
+    if let Some(path) = level {
+        for wave in waves.iter_mut() {
+            wave.level.get_or_insert_with(|| path.clone());
+        }
     }
+    waves
+}
 
-    let amplitudes = core::simd::Simd::<f32, LANES>::from_array(amplitudes);
-    let frequencies = core::simd::Simd::<f32, LANES>::from_array(frequencies);
-    let sample = noise.sample([
-        core::simd::Simd::<f32, LANES>::splat(x) * frequencies,
-        core::simd::Simd::<f32, LANES>::splat(y) * frequencies,
-    ]) * amplitudes;
-
-    sample.value.reduce_sum() / max_value
+/// Used when no seed is given. It's printed so the run can be repeated.
+fn fresh_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
 }
 
 fn get_pointer_world_position(
@@ -2676,6 +1608,56 @@ fn get_pointer_world_position(
     p
 }
 
+fn select_hotbar_slot(simulation: &Simulation, slot: usize) -> Option<Command> {
+    let slot = simulation.hotbar.slots.get(slot)?;
+    Some(Command::SelectItem(slot.item))
+}
+
+/// Replaces `simulation` with the save at `path`, keeping its texture.
+/// Returns false and leaves `simulation` alone if the save couldn't be loaded.
+fn load_save(
+    path: &std::path::Path,
+    config: &SimulationConfig,
+    simulation: &mut Simulation,
+) -> bool {
+    match save::load_from_file(path) {
+        Ok(mut loaded) if loaded.world_size == simulation.world_size => {
+            loaded.grid_world.take_texture_from(&mut simulation.grid_world);
+            loaded.configure_loaded(config);
+            *simulation = loaded;
+            info!("LOADED {:?}", path);
+            true
+        }
+        Ok(_) => {
+            warn!("Could not load {:?}: the world size is different", path);
+            false
+        }
+        Err(e) => {
+            warn!("Could not load {:?}: {}", path, e);
+            false
+        }
+    }
+}
+
+fn save_level(path: &std::path::Path, simulation: &Simulation) {
+    match Level::capture(simulation).save_to_file(path) {
+        Ok(()) => info!("SAVED LEVEL {:?}", path),
+        Err(e) => warn!("Could not save level {:?}: {}", path, e),
+    }
+}
+
+/// Throws away unsaved edits and goes back to the level at `path`.
+fn load_level(path: &std::path::Path, simulation: &mut Simulation) {
+    let loaded = Level::load_from_file(path).map_err(|e| e.to_string());
+    match loaded.and_then(|level| level.apply(simulation)) {
+        Ok(()) => {
+            simulation.enter_editor();
+            info!("LOADED LEVEL {:?}", path);
+        }
+        Err(e) => warn!("Could not load level {:?}: {}", path, e),
+    }
+}
+
 fn get_texture_material(
     path: &str,
     resources: &Resources,
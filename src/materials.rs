@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::grid_world::{PheromoneChannel, TileMaterial};
+
+/// The materials file the game ships with, built in so it still plays the same without it.
+const BUILT_IN: &str = include_str!("../assets/materials.ron");
+
+/// How a [TileMaterial] looks and what it does to things on it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialBehavior {
+    /// An sRGB hex colour, like 0x7A613B.
+    pub color: u32,
+    /// Added to how much an ant wants to avoid the tile. `None` means ants never step on it.
+    pub walk_cost: Option<i32>,
+    /// Foraging enemies pick it up and take it back to the spawn edge.
+    pub carried: bool,
+    /// Health an enemy standing on it loses each tick.
+    pub damage_per_tick: u8,
+    /// Health the player gets back for sucking it up with the straw, which eats it.
+    pub heal_when_sucked: i32,
+    /// A pheromone it gives off each tick, and how much.
+    pub emits: Option<(PheromoneChannel, f32)>,
+    /// The most a tile of it can stand above its lowest neighbour before it slides down into it.
+    /// `None` means it stays put.
+    pub slump: Option<u8>,
+    /// When it slides into a neighbour the neighbour becomes this material too.
+    pub spreads: bool,
+    /// The chance each tick that it turns into another material.
+    pub settles_into: Option<(TileMaterial, f32)>,
+}
+
+impl Default for MaterialBehavior {
+    fn default() -> Self {
+        Self {
+            color: 0xFF00FF,
+            walk_cost: Some(0),
+            carried: false,
+            damage_per_tick: 0,
+            heal_when_sucked: 0,
+            emits: None,
+            slump: None,
+            spreads: false,
+            settles_into: None,
+        }
+    }
+}
+
+impl MaterialBehavior {
+    pub fn color(&self) -> Color {
+        Color::from_srgb_hex(self.color, 1.0)
+    }
+}
+
+/// The behavior of every [TileMaterial].
+/// It's serialized as a map from every material to its behavior, like the materials file.
+#[derive(Clone)]
+pub struct MaterialRegistry {
+    behaviors: Vec<MaterialBehavior>,
+}
+
+impl MaterialRegistry {
+    /// The behavior in the built in copy of `assets/materials.ron`.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            behaviors: vec![MaterialBehavior::default(); TileMaterial::ALL.len()],
+        };
+        registry.apply_overrides(
+            ron::from_str(BUILT_IN).expect("the built in materials.ron should load"),
+        );
+        registry
+    }
+
+    /// Loads a RON map from materials to their behavior, like `{ Nacho: (color: 0xFFA500) }`.
+    /// Materials that aren't in the file keep their built in behavior.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&source)
+    }
+
+    /// Like [MaterialRegistry::load] but from the file's contents.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        ron::from_str(source).map_err(|e| e.to_string())
+    }
+
+    fn apply_overrides(&mut self, overrides: HashMap<TileMaterial, MaterialBehavior>) {
+        for (material, behavior) in overrides {
+            self.behaviors[material as usize] = behavior;
+        }
+    }
+
+    pub fn get(&self, material: TileMaterial) -> &MaterialBehavior {
+        &self.behaviors[material as usize]
+    }
+}
+
+impl Serialize for MaterialRegistry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let behaviors: HashMap<TileMaterial, &MaterialBehavior> = TileMaterial::ALL
+            .iter()
+            .map(|material| (*material, self.get(*material)))
+            .collect();
+        behaviors.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MaterialRegistry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut registry = Self::new();
+        registry.apply_overrides(HashMap::deserialize(deserializer)?);
+        Ok(registry)
+    }
+}
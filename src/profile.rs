@@ -0,0 +1,82 @@
+//! Running totals of how long the hot parts of a tick take, for `benches/simulation.rs`.
+//! Timing is off until [set_enabled] turns it on, and costs next to nothing while it's off.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    /// Every call to [GridWorld::for_tile_in_radius](crate::grid_world::GridWorld::for_tile_in_radius)
+    /// or [TileView::for_tile_in_radius](crate::grid_world::TileView::for_tile_in_radius),
+    /// including the ones that score tiles.
+    ForTileInRadius,
+    /// Ants scoring the tiles around them to pick where to go.
+    Scoring,
+    /// Working out the colours of the tiles that changed, but not uploading them.
+    UpdateTexture,
+}
+
+impl Section {
+    pub const ALL: [Section; 3] = [
+        Section::ForTileInRadius,
+        Section::Scoring,
+        Section::UpdateTexture,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::ForTileInRadius => "for_tile_in_radius",
+            Section::Scoring => "scoring",
+            Section::UpdateTexture => "update_texture",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NANOS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static CALLS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Adds the time until it's dropped to `section`'s total, if timing is on.
+/// Sections can be timed on several threads at once, and their times add up.
+#[must_use]
+pub struct Timer {
+    section: Section,
+    start: Option<Instant>,
+}
+
+pub fn timer(section: Section) -> Timer {
+    Timer {
+        section,
+        start: ENABLED.load(Ordering::Relaxed).then(Instant::now),
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let i = self.section as usize;
+            NANOS[i].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            CALLS[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Total {
+    pub section: Section,
+    pub time: Duration,
+    pub calls: u64,
+}
+
+/// The totals since the last call, which start again from zero.
+pub fn take() -> [Total; 3] {
+    Section::ALL.map(|section| Total {
+        section,
+        time: Duration::from_nanos(NANOS[section as usize].swap(0, Ordering::Relaxed)),
+        calls: CALLS[section as usize].swap(0, Ordering::Relaxed),
+    })
+}
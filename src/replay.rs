@@ -0,0 +1,243 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::file_format::{self, invalid_data, vec2};
+use crate::level::check_world_size;
+use crate::simulation::{Command, Simulation, SimulationConfig, TickInput};
+use crate::waves::check_waves;
+
+/// Recordings from before they were RON started with this.
+const OLD_MAGIC: &[u8; 4] = b"LD52";
+/// Bumped whenever the file changes or the same input would play out differently,
+/// so old recordings are refused rather than played back wrong.
+const VERSION: u32 = 1;
+
+/// A recorded play session: the seed it started from, what the game was set up with,
+/// and the input for every fixed tick.
+///
+/// The file is a line of RON with everything but the ticks, then a line for each tick.
+/// Most ticks are just `()`, the pointer is only written when it moves.
+pub struct Recording {
+    pub seed: u64,
+    pub world_size: Vec2,
+    pub config: SimulationConfig,
+    pub ticks: Vec<TickInput>,
+}
+
+/// The first line of a recording.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    seed: u64,
+    #[serde(with = "vec2")]
+    world_size: Vec2,
+    config: SimulationConfig,
+}
+
+/// One line of a recording after the header. What's false or empty is left out.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct RecordedTick {
+    /// Where the pointer moved to, `None` if it didn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pointer: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "is_false")]
+    pointer_down: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pointer_held: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pointer_released: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    commands: Vec<Command>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Streams ticks to disk as they're played so a crash doesn't lose the session.
+pub struct RecordingWriter {
+    writer: BufWriter<File>,
+    last_pointer: Vec3,
+    ticks_since_flush: u32,
+}
+
+impl RecordingWriter {
+    pub fn create(
+        path: impl AsRef<Path>,
+        seed: u64,
+        world_size: Vec2,
+        config: &SimulationConfig,
+    ) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = Header {
+            seed,
+            world_size,
+            config: config.clone(),
+        };
+        writeln!(writer, "{}", file_format::to_ron(VERSION, &header))?;
+
+        Ok(Self {
+            writer,
+            last_pointer: Vec3::ZERO,
+            ticks_since_flush: 0,
+        })
+    }
+
+    pub fn record(&mut self, input: &TickInput) -> std::io::Result<()> {
+        let pointer = input.pointer;
+        let tick = RecordedTick {
+            pointer: (pointer != self.last_pointer).then(|| [pointer.x, pointer.y, pointer.z]),
+            pointer_down: input.pointer_down,
+            pointer_held: input.pointer_held,
+            pointer_released: input.pointer_released,
+            commands: input.commands.clone(),
+        };
+        self.last_pointer = pointer;
+        writeln!(self.writer, "{}", ron::to_string(&tick).unwrap())?;
+
+        self.ticks_since_flush += 1;
+        if self.ticks_since_flush >= 60 {
+            self.ticks_since_flush = 0;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for RecordingWriter {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.starts_with(OLD_MAGIC) {
+            return Err(invalid_data(
+                "recording is from before recordings were RON and can't be played",
+            ));
+        }
+        let source = std::str::from_utf8(&bytes).map_err(|_| invalid_data("not a recording"))?;
+        let mut lines = source.lines();
+
+        let Header {
+            seed,
+            world_size,
+            config,
+        } = file_format::from_ron(lines.next().unwrap_or_default(), VERSION, "recording")?;
+        check_world_size(world_size.x as usize, world_size.y as usize).map_err(invalid_data)?;
+        check_waves(&config.waves).map_err(|e| invalid_data(e.to_string()))?;
+        for level in config.levels.values() {
+            level.check().map_err(invalid_data)?;
+        }
+
+        let lines: Vec<&str> = lines.collect();
+        let mut ticks = Vec::with_capacity(lines.len());
+        let mut pointer = Vec3::ZERO;
+        for (i, line) in lines.iter().enumerate() {
+            let tick: RecordedTick = match ron::from_str(line) {
+                Ok(tick) => tick,
+                // A recording that was cut off mid-tick just ends at the last whole tick.
+                Err(_) if i + 1 == lines.len() => break,
+                Err(e) => return Err(invalid_data(format!("tick {}: {}", i, e))),
+            };
+            if let Some([x, y, z]) = tick.pointer {
+                pointer = Vec3::new(x, y, z);
+            }
+            ticks.push(TickInput {
+                pointer,
+                pointer_down: tick.pointer_down,
+                pointer_held: tick.pointer_held,
+                pointer_released: tick.pointer_released,
+                commands: tick.commands,
+            });
+        }
+
+        Ok(Self {
+            seed,
+            world_size,
+            config,
+            ticks,
+        })
+    }
+
+    /// A fresh [Simulation] set up like the recorded one was.
+    pub fn start(&self) -> Simulation {
+        let mut simulation = Simulation::new(self.world_size, self.seed);
+        simulation.configure(self.config.clone());
+        simulation
+    }
+
+    /// Steps a fresh [Simulation] through every recorded tick without a window.
+    pub fn play_headless(&self) -> Simulation {
+        let mut simulation = self.start();
+        for tick in self.ticks.iter() {
+            simulation.step(tick);
+        }
+        simulation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{Level, DEFAULT_WORLD_SIZE};
+    use crate::simulation::CurrentItem;
+
+    #[test]
+    fn recordings_load_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!("ld52-test-{}.ld52rec", std::process::id()));
+        let mut config = SimulationConfig::default();
+        let level = Level::capture(&Simulation::new(DEFAULT_WORLD_SIZE, 1));
+        config.levels.insert("levels/test.ld52level".into(), level);
+        config.tile_physics = true;
+
+        let ticks = vec![
+            TickInput::default(),
+            TickInput {
+                pointer: Vec3::new(10.0, 20.5, 0.0),
+                pointer_down: true,
+                pointer_held: true,
+                commands: vec![Command::SelectItem(CurrentItem::Gun), Command::SetHealth(3)],
+                ..TickInput::default()
+            },
+            TickInput {
+                pointer: Vec3::new(10.0, 20.5, 0.0),
+                pointer_released: true,
+                ..TickInput::default()
+            },
+        ];
+        let mut writer = RecordingWriter::create(&path, 52, DEFAULT_WORLD_SIZE, &config).unwrap();
+        for tick in ticks.iter() {
+            writer.record(tick).unwrap();
+        }
+        drop(writer);
+
+        let recording = Recording::load(&path).unwrap();
+        assert_eq!(recording.seed, 52);
+        assert_eq!(recording.world_size, DEFAULT_WORLD_SIZE);
+        assert!(recording.config.tile_physics);
+        assert_eq!(recording.config.levels.len(), 1);
+        assert_eq!(format!("{:?}", recording.ticks), format!("{:?}", ticks));
+
+        // Cut off partway through the last tick.
+        let mut source = std::fs::read_to_string(&path).unwrap();
+        source.truncate(source.trim_end().len() - 3);
+        std::fs::write(&path, source).unwrap();
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            format!("{:?}", recording.ticks),
+            format!("{:?}", &ticks[..2])
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Tools for setting up scenarios by hand in sandbox mode, and for making levels in the editor.
+
+use serde::{Deserialize, Serialize};
+
+use crate::defend::{DefendTarget, DefendTargetKind};
+use crate::enemies::EnemyKindId;
+use crate::grid_world::{PheromoneChannel, TileMaterial, PHEROMONE_MAX};
+use crate::items::{HandArt, Item, ItemContext};
+use crate::simulation::{spawn_ants_with_pattern, CurrentItem, SimEvent, Simulation};
+use crate::waves::SpawnPattern;
+
+const MIN_RADIUS: f32 = 1.0;
+const MAX_RADIUS: f32 = 30.0;
+const MAX_SPAWN_COUNT: i32 = 1000;
+/// How much the middle of the brush raises or lowers the ground each tick.
+const HEIGHT_PER_TICK: f32 = 8.0;
+/// How much pheromone the middle of the brush lays each tick.
+const PHEROMONE_PER_TICK: f32 = 200.0;
+/// How close a click has to be to a spawn point to remove it.
+const SPAWN_POINT_PICK_RADIUS: f32 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SandboxTool {
+    SpawnAnts,
+    SpawnSpiders,
+    PaintMaterial,
+    RaiseHeight,
+    LowerHeight,
+    PaintPheromone,
+    /// Adds a level spawn point, or takes away the one clicked on.
+    PlaceSpawnPoint,
+    /// Adds a [DefendTarget], or takes away the one clicked on.
+    PlaceTarget,
+}
+
+impl SandboxTool {
+    pub const ALL: [SandboxTool; 8] = [
+        SandboxTool::SpawnAnts,
+        SandboxTool::SpawnSpiders,
+        SandboxTool::PaintMaterial,
+        SandboxTool::RaiseHeight,
+        SandboxTool::LowerHeight,
+        SandboxTool::PaintPheromone,
+        SandboxTool::PlaceSpawnPoint,
+        SandboxTool::PlaceTarget,
+    ];
+
+    pub fn next(self) -> Self {
+        next_in(&Self::ALL, self)
+    }
+}
+
+/// Changes to the sandbox tools. They're ignored outside of sandbox mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SandboxCommand {
+    /// Picks a tool and puts the brush in hand.
+    Tool(SandboxTool),
+    NextMaterial,
+    NextChannel,
+    NextTargetKind,
+    /// Grows the brush by this many tiles, or shrinks it if negative.
+    Resize(i32),
+    /// Changes how many enemies are spawned with each click.
+    SpawnMore(i32),
+    /// Removes every enemy and spitball and clears the pheromones. The terrain is left alone.
+    Clear,
+}
+
+/// What the brush does, and the settings for each tool.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SandboxTools {
+    pub tool: SandboxTool,
+    pub material: TileMaterial,
+    pub channel: PheromoneChannel,
+    pub target_kind: DefendTargetKind,
+    pub radius: f32,
+    pub spawn_count: u32,
+}
+
+impl Default for SandboxTools {
+    fn default() -> Self {
+        Self {
+            tool: SandboxTool::PaintMaterial,
+            material: TileMaterial::Earth,
+            channel: PheromoneChannel::Food,
+            target_kind: DefendTargetKind::Feet,
+            radius: 3.0,
+            spawn_count: 10,
+        }
+    }
+}
+
+impl SandboxTools {
+    /// Shown to the player when the tool or its settings change.
+    pub fn describe(&self) -> String {
+        match self.tool {
+            SandboxTool::SpawnAnts | SandboxTool::SpawnSpiders => {
+                format!("{:?} x{}", self.tool, self.spawn_count)
+            }
+            SandboxTool::PaintMaterial => {
+                format!("Paint {:?}, size {}", self.material, self.radius)
+            }
+            SandboxTool::RaiseHeight | SandboxTool::LowerHeight => {
+                format!("{:?}, size {}", self.tool, self.radius)
+            }
+            SandboxTool::PaintPheromone => {
+                format!("Paint {:?} pheromone, size {}", self.channel, self.radius)
+            }
+            SandboxTool::PlaceSpawnPoint => "Place spawn points, click one to remove it".into(),
+            SandboxTool::PlaceTarget => {
+                format!("Place {:?}, click one to remove it", self.target_kind)
+            }
+        }
+    }
+}
+
+pub(crate) fn apply_command(
+    simulation: &mut Simulation,
+    command: SandboxCommand,
+    events: &mut Vec<SimEvent>,
+) {
+    if !simulation.sandbox_mode {
+        return;
+    }
+
+    let tools = &mut simulation.sandbox_tools;
+    match command {
+        SandboxCommand::Tool(tool) => {
+            tools.tool = tool;
+            if simulation.hotbar.can_equip(CurrentItem::Brush) {
+                simulation.hotbar.selected = CurrentItem::Brush;
+            }
+        }
+        SandboxCommand::NextMaterial => {
+            tools.material = next_in(&TileMaterial::ALL, tools.material)
+        }
+        SandboxCommand::NextChannel => {
+            tools.channel = next_in(&PheromoneChannel::ALL, tools.channel)
+        }
+        SandboxCommand::NextTargetKind => {
+            tools.target_kind = next_in(&DefendTargetKind::ALL, tools.target_kind)
+        }
+        SandboxCommand::Resize(amount) => {
+            tools.radius = (tools.radius + amount as f32).clamp(MIN_RADIUS, MAX_RADIUS);
+        }
+        SandboxCommand::SpawnMore(amount) => {
+            tools.spawn_count = (tools.spawn_count as i32)
+                .saturating_add(amount)
+                .clamp(1, MAX_SPAWN_COUNT) as u32;
+        }
+        SandboxCommand::Clear => {
+            simulation.ants.clear();
+            simulation.spitballs.clear();
+            simulation.spawn_queue = Default::default();
+            simulation.grid_world.neutral_pheremones();
+            events.push(SimEvent::BonusText("Cleared".into(), 20.0));
+            return;
+        }
+    }
+    events.push(SimEvent::BonusText(
+        simulation.sandbox_tools.describe(),
+        20.0,
+    ));
+}
+
+fn next_in<T: Copy + PartialEq>(all: &[T], current: T) -> T {
+    let i = all.iter().position(|t| *t == current).unwrap_or(0);
+    all[(i + 1) % all.len()]
+}
+
+/// Uses whichever [SandboxTool] is picked.
+pub struct Brush;
+
+impl Item for Brush {
+    fn hand_art(&self) -> Option<HandArt> {
+        Some(HandArt {
+            idle: "assets/pointedfinger.png",
+            pressed: None,
+            fired: None,
+        })
+    }
+
+    fn aims(&self) -> bool {
+        true
+    }
+
+    fn on_press(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        let pointer = context.input.pointer;
+        let kind = match simulation.sandbox_tools.tool {
+            SandboxTool::SpawnAnts => EnemyKindId::ANT,
+            SandboxTool::SpawnSpiders => EnemyKindId::SPIDER,
+            SandboxTool::PlaceSpawnPoint => {
+                let points = &mut simulation.spawn_points;
+                match points
+                    .iter()
+                    .position(|p| (*p - pointer.xy()).length() < SPAWN_POINT_PICK_RADIUS)
+                {
+                    Some(i) => {
+                        points.remove(i);
+                    }
+                    None => points.push(pointer.xy()),
+                }
+                return;
+            }
+            SandboxTool::PlaceTarget => {
+                let targets = &mut simulation.defend_targets;
+                match targets
+                    .iter()
+                    .position(|t| (t.position - pointer.xy()).length() < t.kind.reach_radius())
+                {
+                    Some(i) => {
+                        targets.remove(i);
+                    }
+                    None => targets.push(DefendTarget::new(
+                        simulation.sandbox_tools.target_kind,
+                        pointer.xy(),
+                    )),
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        let enemy = simulation.enemy_kinds.get(kind);
+        let spawned = spawn_ants_with_pattern(
+            &mut simulation.random,
+            simulation.world_size,
+            kind,
+            &SpawnPattern::Point {
+                x: pointer.x,
+                y: pointer.y,
+                radius: simulation.sandbox_tools.radius,
+            },
+            simulation.sandbox_tools.spawn_count as usize,
+            enemy.speed,
+            (enemy.health, enemy.health),
+        );
+        simulation.ants.extend(spawned);
+    }
+
+    fn on_hold(&self, simulation: &mut Simulation, context: &mut ItemContext) {
+        let tools = &simulation.sandbox_tools;
+        let radius = tools.radius;
+        let pointer = context.input.pointer.xy();
+
+        match tools.tool {
+            SandboxTool::SpawnAnts
+            | SandboxTool::SpawnSpiders
+            | SandboxTool::PlaceSpawnPoint
+            | SandboxTool::PlaceTarget => {}
+            SandboxTool::PaintMaterial => {
+                let material = tools.material;
+                simulation
+                    .grid_world
+                    .for_tile_in_radius(pointer, radius, |_, _, t| {
+                        t.material = material;
+                        true
+                    });
+            }
+            SandboxTool::RaiseHeight | SandboxTool::LowerHeight => {
+                let raise = tools.tool == SandboxTool::RaiseHeight;
+                simulation
+                    .grid_world
+                    .for_tile_in_radius(pointer, radius, |_, r, t| {
+                        let amount = (HEIGHT_PER_TICK * (1.0 - r / radius)).ceil() as u8;
+                        t.height = if raise {
+                            t.height.saturating_add(amount)
+                        } else {
+                            t.height.saturating_sub(amount)
+                        };
+                        true
+                    });
+            }
+            SandboxTool::PaintPheromone => {
+                let channel = tools.channel;
+                simulation
+                    .grid_world
+                    .for_tile_in_radius(pointer, radius, |_, r, t| {
+                        let p = t.pheromones.get_mut(channel);
+                        // Walls stay walls.
+                        if *p < PHEROMONE_MAX {
+                            *p = (*p + PHEROMONE_PER_TICK * (1.0 - r / radius))
+                                .min(PHEROMONE_MAX - 1.0);
+                        }
+                        false
+                    });
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::VecDeque;
+use std::path::Path;
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defend::DefendTarget;
+use crate::file_format::{self, invalid_data, vec2, vec2s, vec3};
+use crate::grid_world::{Biome, GridWorld, PheromoneConfig, Tile, TileMaterial};
+use crate::items::Hotbar;
+use crate::level::check_world_size;
+use crate::replay::Recording;
+use crate::sandbox::SandboxTools;
+use crate::simulation::{Ant, CurrentItem, Simulation, SpawnQueue, SpitBall};
+use crate::slider::Slider;
+use crate::waves::{Wave, WaveStats};
+
+/// Saves from before they were RON started with this.
+const OLD_MAGIC: &[u8; 4] = b"LD5S";
+/// Bumped whenever [SavedGame] changes, or a saved game would play on differently.
+/// Saves of other versions are refused rather than loaded wrong.
+const VERSION: u32 = 1;
+
+/// Everything in a [Simulation] needed to carry on from where it is.
+///
+/// The waves are saved too, so a loaded game carries on with the waves it was started with.
+/// The [MaterialRegistry](crate::materials::MaterialRegistry) and the levels the waves pick aren't,
+/// they're set up with [Simulation::configure_loaded].
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+    #[serde(with = "vec2")]
+    world_size: Vec2,
+    seed: u64,
+    tick: u64,
+    biome: Biome,
+    level: Option<String>,
+    defend_targets: Vec<DefendTarget>,
+    #[serde(with = "vec2s")]
+    spawn_points: Vec<Vec2>,
+
+    tile_physics: bool,
+    pheromone_config: PheromoneConfig,
+    tiles: Vec<Tile>,
+    pheromone_baseline: Vec<f32>,
+
+    ants: Vec<Ant>,
+    spitballs: Vec<SpitBall>,
+
+    player_health: i32,
+    started_once: bool,
+    sandbox_mode: bool,
+    victory: bool,
+    defeated: bool,
+
+    hotbar: Hotbar,
+    sandbox_tools: SandboxTools,
+
+    waves: Vec<Wave>,
+    current_wave: i32,
+    frames_elapsed_since_last_wave: u32,
+    wave_stats: WaveStats,
+    spawn_queue: SpawnQueue,
+    spawn_wave_override: bool,
+    wave_text_shown: bool,
+
+    slider: Slider,
+    rummage_count: i32,
+    rummage_refresh_timer: i32,
+
+    straw_contents: VecDeque<TileMaterial>,
+    straw_sucking: bool,
+    toothpaste_squeeze: f32,
+
+    #[serde(with = "vec3")]
+    pointer_position: Vec3,
+    #[serde(with = "vec3")]
+    pointer_last_position: Vec3,
+    pointer_max_speed: Option<f32>,
+    pointer_held_len: u32,
+}
+
+/// Writes everything needed to carry on from where `simulation` is.
+pub fn save(simulation: &Simulation) -> Vec<u8> {
+    let grid_world = &simulation.grid_world;
+    let game = SavedGame {
+        world_size: simulation.world_size,
+        seed: simulation.seed,
+        tick: simulation.tick,
+        biome: simulation.biome,
+        level: simulation.level.clone(),
+        defend_targets: simulation.defend_targets.clone(),
+        spawn_points: simulation.spawn_points.clone(),
+
+        tile_physics: grid_world.tile_physics,
+        pheromone_config: grid_world.pheromone_config,
+        tiles: grid_world.tiles.clone(),
+        pheromone_baseline: grid_world.pheromone_baseline.clone(),
+
+        ants: simulation.ants.clone(),
+        spitballs: simulation.spitballs.clone(),
+
+        player_health: simulation.player_health,
+        started_once: simulation.started_once,
+        sandbox_mode: simulation.sandbox_mode,
+        victory: simulation.victory,
+        defeated: simulation.defeated,
+
+        hotbar: simulation.hotbar.clone(),
+        sandbox_tools: simulation.sandbox_tools.clone(),
+
+        waves: simulation.waves.clone(),
+        current_wave: simulation.current_wave,
+        frames_elapsed_since_last_wave: simulation.frames_elapsed_since_last_wave,
+        wave_stats: simulation.wave_stats,
+        spawn_queue: simulation.spawn_queue.clone(),
+        spawn_wave_override: simulation.spawn_wave_override,
+        wave_text_shown: simulation.wave_text_shown,
+
+        slider: simulation.slider.clone(),
+        rummage_count: simulation.rummage_count,
+        rummage_refresh_timer: simulation.rummage_refresh_timer,
+
+        straw_contents: simulation.straw_contents.clone(),
+        straw_sucking: simulation.straw_sucking,
+        toothpaste_squeeze: simulation.toothpaste_squeeze,
+
+        pointer_position: simulation.pointer_position,
+        pointer_last_position: simulation.pointer_last_position,
+        pointer_max_speed: simulation.pointer_max_speed,
+        pointer_held_len: simulation.pointer_held_len,
+    };
+
+    file_format::to_ron(VERSION, &game).into_bytes()
+}
+
+pub fn load(bytes: &[u8]) -> std::io::Result<Simulation> {
+    if bytes.starts_with(OLD_MAGIC) {
+        return Err(invalid_data(
+            "save is from before saves were RON and can't be loaded",
+        ));
+    }
+    let source = std::str::from_utf8(bytes).map_err(|_| invalid_data("not a save"))?;
+    let game: SavedGame = file_format::from_ron(source, VERSION, "save")?;
+
+    let SavedGame {
+        world_size,
+        seed,
+        tick,
+        biome,
+        level,
+        defend_targets,
+        spawn_points,
+        tile_physics,
+        pheromone_config,
+        tiles,
+        pheromone_baseline,
+        ants,
+        spitballs,
+        player_health,
+        started_once,
+        sandbox_mode,
+        victory,
+        defeated,
+        hotbar,
+        sandbox_tools,
+        waves,
+        current_wave,
+        frames_elapsed_since_last_wave,
+        wave_stats,
+        spawn_queue,
+        spawn_wave_override,
+        wave_text_shown,
+        slider,
+        rummage_count,
+        rummage_refresh_timer,
+        straw_contents,
+        straw_sucking,
+        toothpaste_squeeze,
+        pointer_position,
+        pointer_last_position,
+        pointer_max_speed,
+        pointer_held_len,
+    } = game;
+
+    let (width, height) = (world_size.x as usize, world_size.y as usize);
+    check_world_size(width, height).map_err(invalid_data)?;
+    if tiles.len() != width * height || pheromone_baseline.len() != tiles.len() {
+        return Err(invalid_data("the tiles in the save don't fit its world"));
+    }
+    if !CurrentItem::ALL
+        .iter()
+        .all(|item| hotbar.slots.iter().any(|slot| slot.item == *item))
+    {
+        return Err(invalid_data("hotbar in save is missing an item"));
+    }
+
+    let mut simulation = Simulation::new(world_size, seed);
+    let kind_count = simulation.enemy_kinds.iter().count();
+    if ants
+        .iter()
+        .chain(spawn_queue.ants.iter())
+        .any(|ant| ant.kind.0 >= kind_count)
+    {
+        return Err(invalid_data("unknown enemy kind in save"));
+    }
+
+    let mut grid_world = GridWorld::new(width, height);
+    grid_world.tile_physics = tile_physics;
+    grid_world.pheromone_config = pheromone_config;
+    grid_world.tiles = tiles;
+    grid_world.pheromone_baseline = pheromone_baseline;
+
+    simulation.tick = tick;
+    simulation.biome = biome;
+    simulation.level = level;
+    simulation.defend_targets = defend_targets;
+    simulation.spawn_points = spawn_points;
+    simulation.grid_world = grid_world;
+    simulation.ants = ants;
+    simulation.spitballs = spitballs;
+    simulation.player_health = player_health;
+    simulation.started_once = started_once;
+    simulation.sandbox_mode = sandbox_mode;
+    simulation.victory = victory;
+    simulation.defeated = defeated;
+    simulation.hotbar = hotbar;
+    simulation.sandbox_tools = sandbox_tools;
+    simulation.waves = waves;
+    simulation.current_wave = current_wave;
+    simulation.frames_elapsed_since_last_wave = frames_elapsed_since_last_wave;
+    simulation.wave_stats = wave_stats;
+    simulation.spawn_queue = spawn_queue;
+    simulation.spawn_wave_override = spawn_wave_override;
+    simulation.wave_text_shown = wave_text_shown;
+    simulation.slider = slider;
+    simulation.rummage_count = rummage_count;
+    simulation.rummage_refresh_timer = rummage_refresh_timer;
+    simulation.straw_contents = straw_contents;
+    simulation.straw_sucking = straw_sucking;
+    simulation.toothpaste_squeeze = toothpaste_squeeze;
+    simulation.pointer_position = pointer_position;
+    simulation.pointer_last_position = pointer_last_position;
+    simulation.pointer_max_speed = pointer_max_speed;
+    simulation.pointer_held_len = pointer_held_len;
+
+    Ok(simulation)
+}
+
+pub fn save_to_file(simulation: &Simulation, path: impl AsRef<Path>) -> std::io::Result<()> {
+    std::fs::write(path, save(simulation))
+}
+
+pub fn load_from_file(path: impl AsRef<Path>) -> std::io::Result<Simulation> {
+    load(&std::fs::read(path)?)
+}
+
+/// Plays `recording` up to `at_tick`, saves, loads the save, then plays the rest of the recording
+/// on both the original and the loaded copy. Returns whether they ended up the same.
+pub fn check_round_trip(recording: &Recording, at_tick: usize) -> std::io::Result<bool> {
+    let mut simulation = recording.start();
+
+    let (before, after) = recording.ticks.split_at(at_tick.min(recording.ticks.len()));
+    for tick in before {
+        simulation.step(tick);
+    }
+
+    let saved = save(&simulation);
+    let mut loaded = load(&saved)?;
+    loaded.configure_loaded(&recording.config);
+    if save(&loaded) != saved {
+        return Ok(false);
+    }
+
+    for tick in after {
+        simulation.step(tick);
+        loaded.step(tick);
+    }
+    Ok(save(&simulation) == save(&loaded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::DEFAULT_WORLD_SIZE;
+    use crate::simulation::{Command, SimulationConfig, TickInput};
+
+    #[test]
+    fn loaded_game_plays_on_the_same() {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, 52);
+        let start = TickInput {
+            commands: vec![Command::Rummage { key_up: false }],
+            ..TickInput::default()
+        };
+        simulation.step(&start);
+        for _ in 0..300 {
+            simulation.step(&TickInput::default());
+        }
+        assert!(!simulation.ants.is_empty());
+
+        let saved = save(&simulation);
+        let mut loaded = load(&saved).unwrap();
+        loaded.configure_loaded(&SimulationConfig::default());
+        assert!(save(&loaded) == saved, "saving a loaded game changed it");
+
+        for _ in 0..300 {
+            simulation.step(&TickInput::default());
+            loaded.step(&TickInput::default());
+        }
+        assert!(
+            save(&simulation) == save(&loaded),
+            "the loaded game played out differently"
+        );
+    }
+}
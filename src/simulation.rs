@@ -0,0 +1,1572 @@
+use std::collections::{HashMap, VecDeque};
+
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+use crate::defend::DefendTarget;
+use crate::enemies::{EnemyKindId, EnemyRegistry};
+use crate::flow_field::{FlowField, UNREACHABLE};
+use crate::grid_world::{Biome, GridWorld, PheromoneConfig, TileMaterial, TileView};
+use crate::items::{self, Hotbar, ItemContext};
+use crate::level::{self, Level};
+use crate::materials::MaterialRegistry;
+use crate::profile::{self, Section};
+use crate::sandbox::{self, SandboxCommand, SandboxTools};
+use crate::slider::{Slider, SliderOption};
+use crate::spatial_hash::SpatialHash;
+use crate::waves::{default_waves, Edge, SpawnPattern, Wave, WaveStats, WaveTrigger};
+use crate::{debug, warn};
+
+pub const PLAYER_HEALTH_MAX: i32 = 25;
+pub const TILES_IN_SPITBALL: usize = 16;
+pub const STRAW_CAPACITY: usize = TILES_IN_SPITBALL * 15;
+
+/// How much ammo [Command::GrantItem] gives.
+pub const GRANTED_AMMO: i32 = 100;
+
+const RUMMAGE_REFRESH_RESET: i32 = 60 * 20;
+const SPATIAL_HASH_CELL_SIZE: f32 = 4.0;
+/// How close ants get before they start steering away from each other.
+const CROWD_RADIUS: f32 = 1.5;
+/// How many ticks apart [WaveTrigger::MaterialTilesAbove] counts the tiles,
+/// since it looks at every one.
+const MATERIAL_COUNT_INTERVAL: u32 = 30;
+/// The fewest ants a thread is given to steer at once.
+#[cfg(feature = "parallel")]
+const ANTS_PER_TASK: usize = 64;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum CurrentItem {
+    Finger,
+    Straw,
+    Nacho,
+    Toothpaste,
+    Gun,
+    Spider,
+    Inspector,
+    /// The sandbox tools, see [crate::sandbox].
+    Brush,
+}
+
+impl CurrentItem {
+    pub const ALL: [CurrentItem; 8] = [
+        CurrentItem::Finger,
+        CurrentItem::Straw,
+        CurrentItem::Nacho,
+        CurrentItem::Toothpaste,
+        CurrentItem::Gun,
+        CurrentItem::Spider,
+        CurrentItem::Inspector,
+        CurrentItem::Brush,
+    ];
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Ant {
+    #[serde(with = "crate::file_format::vec3")]
+    pub position: Vec3,
+    /// The angle the ant is facing. Only used for display.
+    pub facing: f32,
+    #[serde(with = "crate::file_format::vec2")]
+    pub dir: Vec2,
+    pub speed: f32,
+    pub return_mode: bool,
+    pub health: u8,
+    pub carrying: Option<TileMaterial>,
+    pub kind: EnemyKindId,
+    /// Where it spawned.
+    #[serde(with = "crate::file_format::vec2")]
+    pub home: Vec2,
+}
+
+/// How [Simulation::step] updates the ants. Both play out exactly the same, tick for tick.
+///
+/// Every ant picks where to go from the tiles as they were at the start of the tick,
+/// with its own random numbers. What they do to the tiles is held back until they've
+/// all picked, then done in ant order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AntUpdate {
+    /// The ants are steered one after another.
+    Serial,
+    /// The ants are steered on several threads when built with the `parallel` feature,
+    /// and one after another without it.
+    Parallel,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SpitBall {
+    #[serde(with = "crate::file_format::vec3")]
+    pub position: Vec3,
+    #[serde(with = "crate::file_format::vec2")]
+    pub target: Vec2,
+    pub material: Vec<TileMaterial>,
+}
+
+/// Player input that happened between two fixed ticks.
+#[derive(Clone, Debug)]
+pub struct TickInput {
+    /// The pointer in world space.
+    pub pointer: Vec3,
+    pub pointer_down: bool,
+    pub pointer_held: bool,
+    pub pointer_released: bool,
+    pub commands: Vec<Command>,
+}
+
+impl Default for TickInput {
+    fn default() -> Self {
+        Self {
+            pointer: Vec3::ZERO,
+            pointer_down: false,
+            pointer_held: false,
+            pointer_released: false,
+            commands: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    SelectItem(CurrentItem),
+    Rummage {
+        key_up: bool,
+    },
+    ForceWave,
+    EnterSandbox,
+    DebugHurt,
+    /// Asks for the game to be saved at the end of the tick. See [SimEvent::SaveRequested].
+    Save,
+    /// A cheat.
+    SetHealth(i32),
+    /// A cheat that hands over an item and [GRANTED_AMMO] of its ammo.
+    GrantItem(CurrentItem),
+    /// A cheat that clears the world and starts the wave at this index.
+    SkipToWave(i32),
+    Sandbox(SandboxCommand),
+}
+
+/// Things that happened during a tick that the game plays sounds or effects for.
+#[derive(Clone, Debug)]
+pub enum SimEvent {
+    AntKilled,
+    AntSucked,
+    AntReachedFeet,
+    NachoSucked,
+    Spit,
+    GunFired,
+    OutOfBullets,
+    FootShot,
+    DebugHurt,
+    Defeated,
+    Restarted,
+    SandboxStarted,
+    WaveText(Vec<String>),
+    WaveSpawned,
+    BonusText(String, f32),
+    BonusTextDuration(f32),
+    /// The simulation is ready to be written out with [crate::save::save].
+    SaveRequested,
+}
+
+/// All of the game's state that isn't presentation.
+/// This can be stepped without a window, renderer, or audio.
+pub struct Simulation {
+    pub world_size: Vec2,
+    pub grid_world: GridWorld,
+    /// What the terrain was last generated as.
+    pub biome: Biome,
+    /// The path of the level last laid over the terrain, if one has been since it was generated.
+    pub level: Option<String>,
+    /// The levels waves can pick, keyed by their path. Loaded up front like `materials`.
+    pub levels: HashMap<String, Level>,
+    /// What the ants are trying to get to. Any of them getting there hurts the player.
+    pub defend_targets: Vec<DefendTarget>,
+    /// Only kept up to date while there are enemies that follow it. It isn't saved,
+    /// it's worked out again from the tiles.
+    pub flow_field: FlowField,
+    /// Where [SpawnPattern::LevelSpawns] waves come from.
+    pub spawn_points: Vec<Vec2>,
+    pub ants: Vec<Ant>,
+    /// A setting rather than game state, so it isn't saved.
+    pub ant_update: AntUpdate,
+    /// Where every ant was at the start of the tick.
+    pub ant_hash: SpatialHash,
+    /// Like `ant_hash` but only the ants that hunters go after.
+    pub prey_hash: SpatialHash,
+    pub spitballs: Vec<SpitBall>,
+    pub enemy_kinds: EnemyRegistry,
+    pub materials: MaterialRegistry,
+    /// The seed the game started from. Each tick's random numbers come from it and `tick`.
+    pub seed: u64,
+    /// How many ticks have been stepped.
+    pub tick: u64,
+    pub random: Random,
+
+    pub player_health: i32,
+    pub started_once: bool,
+    pub sandbox_mode: bool,
+    pub victory: bool,
+    pub defeated: bool,
+
+    pub hotbar: Hotbar,
+    pub sandbox_tools: SandboxTools,
+
+    pub waves: Vec<Wave>,
+    pub current_wave: i32,
+    pub frames_elapsed_since_last_wave: u32,
+    pub wave_stats: WaveStats,
+    pub spawn_queue: SpawnQueue,
+    pub spawn_wave_override: bool,
+    pub wave_text_shown: bool,
+
+    pub slider: Slider,
+    pub rummage_count: i32,
+    pub rummage_refresh_timer: i32,
+
+    pub straw_contents: VecDeque<TileMaterial>,
+    pub straw_sucking: bool,
+    pub toothpaste_squeeze: f32,
+
+    /// Where the player's hand is, which lags behind the pointer.
+    pub pointer_position: Vec3,
+    pub pointer_last_position: Vec3,
+    pub pointer_max_speed: Option<f32>,
+    pub pointer_held_len: u32,
+
+    pub save_requested: bool,
+}
+
+/// Ants waiting to be let into the world, for waves that spawn over time.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SpawnQueue {
+    pub ants: VecDeque<Ant>,
+    pub per_tick: f32,
+    /// Fractions of an ant left over from previous ticks.
+    pub accumulated: f32,
+}
+
+/// What a game is set up with besides its seed and world size, mostly loaded from `assets`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SimulationConfig {
+    pub waves: Vec<Wave>,
+    /// The levels the waves pick, keyed by their path.
+    pub levels: HashMap<String, Level>,
+    pub pheromone_config: PheromoneConfig,
+    pub materials: MaterialRegistry,
+    pub tile_physics: bool,
+    pub ant_update: AntUpdate,
+}
+
+impl Default for SimulationConfig {
+    /// What [Simulation::new] starts with.
+    fn default() -> Self {
+        Self {
+            waves: default_waves(),
+            levels: HashMap::new(),
+            pheromone_config: PheromoneConfig::default(),
+            materials: MaterialRegistry::new(),
+            tile_physics: false,
+            ant_update: AntUpdate::Serial,
+        }
+    }
+}
+
+/// Lines ants up along the top edge.
+pub fn spawn_ants(ants: &mut Vec<Ant>, random: &mut Random, world_size: Vec2, ant_count: usize) {
+    ants.extend(spawn_ants_with_pattern(
+        random,
+        world_size,
+        EnemyKindId::ANT,
+        &SpawnPattern::Edge(Edge::Top),
+        ant_count,
+        (0.05, 0.2),
+        (255, 255),
+    ));
+}
+
+pub fn spawn_ants_with_pattern(
+    random: &mut Random,
+    world_size: Vec2,
+    kind: EnemyKindId,
+    pattern: &SpawnPattern,
+    ant_count: usize,
+    speed: (f32, f32),
+    health: (u8, u8),
+) -> Vec<Ant> {
+    let mut ants = Vec::with_capacity(ant_count);
+
+    for i in 0..ant_count {
+        let along = i as f32 / ant_count as f32;
+
+        let position = match pattern {
+            SpawnPattern::Edge(Edge::Top) => Vec2::new(along * world_size.x, world_size.y - 5.0),
+            SpawnPattern::Edge(Edge::Bottom) => Vec2::new(along * world_size.x, 5.0),
+            SpawnPattern::Edge(Edge::Left) => Vec2::new(5.0, along * world_size.y),
+            SpawnPattern::Edge(Edge::Right) => Vec2::new(world_size.x - 5.0, along * world_size.y),
+            SpawnPattern::Point { x, y, radius } => {
+                Vec2::new(*x, *y) + random_in_circle(random, *radius)
+            }
+            SpawnPattern::Scatter { min, max } => Vec2::new(
+                random_in_range(random, min.0, max.0),
+                random_in_range(random, min.1, max.1),
+            ),
+            SpawnPattern::Burrows { holes, radius } => match holes.get(i % holes.len().max(1)) {
+                Some(hole) => Vec2::new(hole.0, hole.1) + random_in_circle(random, *radius),
+                None => Vec2::new(along * world_size.x, world_size.y - 5.0),
+            },
+            // There's no level here to take the holes from, so it's a `Burrows` with none.
+            SpawnPattern::LevelSpawns { .. } => Vec2::new(along * world_size.x, world_size.y - 5.0),
+        };
+        // Holes near the edge can put ants a radius off the grid.
+        let position = position.max(Vec2::ZERO).min(world_size - Vec2::ONE);
+
+        ants.push(Ant {
+            position: position.extend(-3.0),
+            facing: std::f32::consts::PI / 2.0,
+            dir: -Vec2::Y,
+            speed: random_in_range(random, speed.0, speed.1),
+            return_mode: false,
+            health: random_in_range(random, health.0 as f32, health.1 as f32).round() as u8,
+            carrying: None,
+            kind,
+            home: position,
+        });
+    }
+    ants
+}
+
+/// Mixes the tick into the seed so neighbouring ticks don't get similar seeds.
+fn tick_seed(seed: u64, tick: u64) -> u64 {
+    // The finishing steps of SplitMix64.
+    let mut z = seed ^ tick.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// How far `position` is from where `pattern` puts ants, zero inside it.
+fn distance_from_spawn(pattern: &SpawnPattern, world_size: Vec2, position: Vec2) -> f32 {
+    let distance_from_circle = |center: Vec2, radius: f32| (position - center).length() - radius;
+
+    let distance = match pattern {
+        SpawnPattern::Edge(Edge::Top) => world_size.y - position.y,
+        SpawnPattern::Edge(Edge::Bottom) => position.y,
+        SpawnPattern::Edge(Edge::Left) => position.x,
+        SpawnPattern::Edge(Edge::Right) => world_size.x - position.x,
+        SpawnPattern::Point { x, y, radius } => distance_from_circle(Vec2::new(*x, *y), *radius),
+        SpawnPattern::Scatter { min, max } => {
+            let min = Vec2::new(min.0, min.1);
+            let max = Vec2::new(max.0, max.1);
+            (position - position.max(min).min(max)).length()
+        }
+        SpawnPattern::Burrows { holes, radius } if !holes.is_empty() => holes
+            .iter()
+            .map(|hole| distance_from_circle(Vec2::new(hole.0, hole.1), *radius))
+            .fold(f32::MAX, f32::min),
+        // These spawn along the top, see [spawn_ants_with_pattern].
+        SpawnPattern::Burrows { .. } | SpawnPattern::LevelSpawns { .. } => {
+            world_size.y - position.y
+        }
+    };
+    distance.max(0.0)
+}
+
+/// A number from `min` to `max`, or `min` if there's nothing between them.
+pub(crate) fn random_in_range(random: &mut Random, min: f32, max: f32) -> f32 {
+    if max > min {
+        random.range_f32(min..max)
+    } else {
+        min
+    }
+}
+
+fn random_in_circle(random: &mut Random, radius: f32) -> Vec2 {
+    let angle = random.range_f32(0.0..std::f32::consts::TAU);
+    let distance = radius * random.f32().sqrt();
+    Vec2::new(angle.cos(), angle.sin()) * distance
+}
+
+impl Simulation {
+    /// Every random choice the simulation makes comes from `seed`,
+    /// so the same seed and the same inputs always play out the same way.
+    pub fn new(world_size: Vec2, seed: u64) -> Self {
+        let mut random = Random::new_with_seed(seed);
+
+        let defend_targets = vec![DefendTarget::default_feet(world_size)];
+        let mut grid_world = GridWorld::new(world_size.x as _, world_size.y as _);
+        grid_world.initial_terrain_setup(
+            &mut random,
+            &Biome::default().terrain_config(),
+            &defend_targets,
+        );
+        grid_world.reverse_deterent_pheromones();
+
+        let mut ants = Vec::new();
+        // Setup a few ants for the first screen.
+        spawn_ants(&mut ants, &mut random, world_size, 10);
+
+        let mut slider = Slider::new();
+        slider.hide();
+
+        Self {
+            world_size,
+            grid_world,
+            biome: Biome::default(),
+            level: None,
+            levels: HashMap::new(),
+            defend_targets,
+            flow_field: FlowField::new(),
+            spawn_points: Vec::new(),
+            ants,
+            ant_update: AntUpdate::Serial,
+            ant_hash: SpatialHash::new(world_size, SPATIAL_HASH_CELL_SIZE),
+            prey_hash: SpatialHash::new(world_size, SPATIAL_HASH_CELL_SIZE),
+            spitballs: Vec::new(),
+            enemy_kinds: EnemyRegistry::new(),
+            materials: MaterialRegistry::new(),
+            seed,
+            tick: 0,
+            random,
+
+            player_health: PLAYER_HEALTH_MAX,
+            started_once: false,
+            sandbox_mode: false,
+            victory: false,
+            defeated: false,
+
+            hotbar: Hotbar::new(),
+            sandbox_tools: SandboxTools::default(),
+
+            waves: default_waves(),
+            current_wave: -1,
+            frames_elapsed_since_last_wave: 0,
+            wave_stats: WaveStats::default(),
+            spawn_queue: SpawnQueue::default(),
+            spawn_wave_override: false,
+            wave_text_shown: false,
+
+            slider,
+            rummage_count: 0,
+            rummage_refresh_timer: 0,
+
+            straw_contents: VecDeque::new(),
+            straw_sucking: false,
+            toothpaste_squeeze: 2.0,
+
+            pointer_position: Vec3::ZERO,
+            pointer_last_position: Vec3::ZERO,
+            pointer_max_speed: None,
+            pointer_held_len: 0,
+
+            save_requested: false,
+        }
+    }
+
+    /// Sets up a new game with `config`.
+    pub fn configure(&mut self, config: SimulationConfig) {
+        self.waves = config.waves;
+        self.levels = config.levels;
+        self.grid_world.pheromone_config = config.pheromone_config;
+        self.materials = config.materials;
+        self.grid_world.tile_physics = config.tile_physics;
+        self.ant_update = config.ant_update;
+    }
+
+    /// Sets up a loaded game with what saves don't keep from `config`.
+    /// Its waves, pheromones and tile physics are the ones it was saved with,
+    /// so any levels its own waves pick that `config` doesn't have are loaded.
+    pub fn configure_loaded(&mut self, config: &SimulationConfig) {
+        self.materials = config.materials.clone();
+        self.levels = config.levels.clone();
+        level::load_missing_levels(&mut self.levels, &self.waves);
+        self.ant_update = config.ant_update;
+    }
+
+    /// The player has lost and is looking at the game over screen.
+    pub fn is_game_over(&self) -> bool {
+        !self.victory && !self.sandbox_mode && self.player_health <= 0
+    }
+
+    fn out_of_that(events: &mut Vec<SimEvent>) {
+        events.push(SimEvent::BonusText("I'm out of that".into(), 50.0));
+    }
+
+    fn select_item(&mut self, item: CurrentItem, events: &mut Vec<SimEvent>) {
+        if !self.hotbar.can_equip(item) {
+            Self::out_of_that(events);
+            return;
+        }
+        self.hotbar.selected = item;
+        self.pointer_max_speed = None;
+        items::item(item).equip(self);
+    }
+
+    fn restart(&mut self) {
+        self.rummage_refresh_timer = 0;
+
+        self.current_wave = -1;
+        self.ants.clear();
+        self.spawn_queue = SpawnQueue::default();
+
+        // The first wave's biome is set up straight away so it's there when the wave arrives.
+        self.biome = self
+            .waves
+            .first()
+            .and_then(|wave| wave.biome)
+            .unwrap_or_default();
+        self.grid_world.initial_terrain_setup(
+            &mut self.random,
+            &self.biome.terrain_config(),
+            &self.defend_targets,
+        );
+        self.level = None;
+        if let Some(path) = self.waves.first().and_then(|wave| wave.level.clone()) {
+            self.use_level(&path);
+        }
+        self.player_health = PLAYER_HEALTH_MAX;
+        self.frames_elapsed_since_last_wave = 0;
+        self.slider.clear();
+        self.wave_text_shown = false;
+        self.rummage_count = 0;
+        self.straw_contents.clear();
+        self.straw_sucking = false;
+        self.defeated = false;
+        self.hotbar = Hotbar::new();
+
+        self.slider.hide();
+    }
+
+    fn rummage(&mut self, key_up: bool, events: &mut Vec<SimEvent>) {
+        if !self.started_once && !key_up {
+            self.grid_world
+                .reset_pheromones(&mut self.random, &self.defend_targets);
+            self.started_once = true;
+            return;
+        }
+
+        // RESET AND RESTART THE WORLD
+        if !self.sandbox_mode && self.player_health <= 0 {
+            self.restart();
+            events.push(SimEvent::Restarted);
+            return;
+        }
+
+        let option = self.slider.stop_line();
+
+        if let Some(o) = option {
+            match o {
+                SliderOption::Bullets => {
+                    self.hotbar.add_ammo(CurrentItem::Gun, 20);
+                    events.push(SimEvent::BonusText("Found 20 BULLETS".into(), 10.0));
+                }
+                SliderOption::Gun => {
+                    self.hotbar.give(CurrentItem::Gun);
+                    self.hotbar.add_ammo(CurrentItem::Gun, 10);
+                    events.push(SimEvent::BonusText(
+                        "Found a GUN in my pocket\nPress 4 to equip".into(),
+                        60.0,
+                    ));
+                }
+                SliderOption::Nachos => {
+                    self.hotbar.add_ammo(CurrentItem::Nacho, 6);
+                    events.push(SimEvent::BonusText(
+                        "\"Chip crumbs. Perfect. \"\nPress 5 to equip".into(),
+                        20.0,
+                    ));
+                }
+                SliderOption::Toothpaste => {
+                    self.hotbar.give(CurrentItem::Toothpaste);
+                    self.hotbar.add_ammo(CurrentItem::Toothpaste, 200);
+                    events.push(SimEvent::BonusText(
+                        "\"Toothpaste. Minty fresh barriers. \"\nPress 3 to equip".into(),
+                        20.0,
+                    ));
+                }
+            }
+            self.rummage_count -= 1;
+        }
+
+        if self.slider.visible {
+            events.push(SimEvent::BonusTextDuration(10.0));
+            self.slider.hide();
+        } else if !key_up {
+            self.slider.show();
+        }
+        debug!("STOPPED ON: {:?}", option);
+    }
+
+    fn enter_sandbox(&mut self, events: &mut Vec<SimEvent>) {
+        if self.started_once {
+            return;
+        }
+
+        self.hotbar.fill();
+        self.sandbox_mode = true;
+        self.started_once = true;
+        self.grid_world.neutral_pheremones();
+        spawn_ants(&mut self.ants, &mut self.random, self.world_size, 200);
+
+        events.push(SimEvent::SandboxStarted);
+        events.push(SimEvent::BonusText("Welcome to SANDBOX MODE".into(), 200.0));
+    }
+
+    /// Sandbox mode with no enemies and the brush in hand, for making a [Level].
+    pub fn enter_editor(&mut self) {
+        self.hotbar.fill();
+        self.hotbar.selected = CurrentItem::Brush;
+        self.sandbox_mode = true;
+        self.started_once = true;
+        self.ants.clear();
+        self.spawn_queue = SpawnQueue::default();
+        self.grid_world.neutral_pheremones();
+        // Otherwise the terrain slumps while it's being shaped.
+        self.grid_world.tile_physics = false;
+    }
+
+    pub fn apply_command(&mut self, command: Command, events: &mut Vec<SimEvent>) {
+        match command {
+            Command::SelectItem(item) => self.select_item(item, events),
+            Command::Rummage { key_up } => self.rummage(key_up, events),
+            Command::ForceWave => self.spawn_wave_override = true,
+            Command::EnterSandbox => self.enter_sandbox(events),
+            Command::Save => self.save_requested = true,
+            Command::DebugHurt => {
+                self.player_health -= 1;
+                debug!("HEALTH: {:?}", self.player_health);
+                self.grid_world
+                    .reset_pheromones(&mut self.random, &self.defend_targets);
+                events.push(SimEvent::DebugHurt);
+            }
+            Command::SetHealth(health) => {
+                self.player_health = health;
+                debug!("HEALTH: {:?}", self.player_health);
+            }
+            Command::GrantItem(item) => {
+                self.hotbar.give(item);
+                self.hotbar.add_ammo(item, GRANTED_AMMO);
+            }
+            Command::SkipToWave(wave) => self.skip_to_wave(wave),
+            Command::Sandbox(command) => sandbox::apply_command(self, command, events),
+        }
+    }
+
+    fn skip_to_wave(&mut self, wave: i32) {
+        if !self.started_once {
+            self.grid_world
+                .reset_pheromones(&mut self.random, &self.defend_targets);
+            self.started_once = true;
+        }
+
+        self.ants.clear();
+        self.spawn_queue = SpawnQueue::default();
+        self.current_wave = wave.clamp(0, self.waves.len() as i32) - 1;
+        self.spawn_wave_override = true;
+        // Don't wait out the wave's delay.
+        self.frames_elapsed_since_last_wave = self
+            .waves
+            .get(wave.max(0) as usize)
+            .map_or(0, |wave| wave.frame_delay as u32);
+    }
+
+    /// Lays the level at `path` over the terrain unless it's the one already there.
+    fn use_level(&mut self, path: &str) {
+        if self.level.as_deref() == Some(path) {
+            return;
+        }
+        let Some(level) = self.levels.get(path).cloned() else {
+            warn!("Level {:?} isn't loaded", path);
+            return;
+        };
+        match level.apply(self) {
+            Ok(()) => self.level = Some(path.to_string()),
+            Err(e) => warn!("Could not use level {:?}: {}", path, e),
+        }
+    }
+
+    /// Swaps [SpawnPattern::LevelSpawns] for the holes it stands for.
+    fn resolve_spawn_pattern(&self, pattern: &SpawnPattern) -> SpawnPattern {
+        match pattern {
+            SpawnPattern::LevelSpawns { radius } => SpawnPattern::Burrows {
+                holes: self.spawn_points.iter().map(|p| (p.x, p.y)).collect(),
+                radius: *radius,
+            },
+            pattern => pattern.clone(),
+        }
+    }
+
+    fn trigger_met(&self, trigger: &WaveTrigger) -> bool {
+        match trigger {
+            WaveTrigger::LowAntCount(c) => *c >= self.ants.len() + self.spawn_queue.ants.len(),
+            WaveTrigger::LowAntCountAwayFromSpawn(c) => {
+                let spawn = match self.waves.get(self.current_wave as usize) {
+                    Some(wave) => self.resolve_spawn_pattern(&wave.spawn),
+                    None => SpawnPattern::Edge(Edge::Top),
+                };
+                let count = self
+                    .ants
+                    .iter()
+                    .filter(|ant| {
+                        distance_from_spawn(&spawn, self.world_size, ant.position.xy()) > 10.0
+                    })
+                    .count();
+                *c >= count + self.spawn_queue.ants.len()
+            }
+            WaveTrigger::FramesElapsedSinceLast(c) => *c <= self.frames_elapsed_since_last_wave,
+            WaveTrigger::AntsKilled(c) => *c <= self.wave_stats.ants_killed,
+            WaveTrigger::AntsReachedFeet(c) => *c <= self.wave_stats.ants_reached_feet,
+            WaveTrigger::CrumbsEaten(c) => *c <= self.wave_stats.crumbs_eaten,
+            WaveTrigger::PlayerHealthBelow(c) => self.player_health < *c,
+            WaveTrigger::MaterialTilesAbove(material, c) => {
+                self.frames_elapsed_since_last_wave
+                    .is_multiple_of(MATERIAL_COUNT_INTERVAL)
+                    && self
+                        .grid_world
+                        .tiles
+                        .iter()
+                        .filter(|t| t.material == *material)
+                        .count()
+                        > *c
+            }
+            WaveTrigger::All(triggers) => triggers.iter().all(|t| self.trigger_met(t)),
+            WaveTrigger::Any(triggers) => triggers.iter().any(|t| self.trigger_met(t)),
+        }
+    }
+
+    fn update_waves(&mut self, events: &mut Vec<SimEvent>) {
+        self.rummage_refresh_timer -= 1;
+        if self.rummage_refresh_timer <= 0 && !self.slider.visible {
+            let random_amount_to_stash = self.random.range_u32(2..10);
+
+            for _ in 0..(random_amount_to_stash.saturating_sub(4)) {
+                self.slider.insert_random_lost_entry(&mut self.random);
+            }
+
+            self.rummage_refresh_timer = RUMMAGE_REFRESH_RESET;
+        }
+
+        // Trigger waves
+        self.frames_elapsed_since_last_wave += 1;
+
+        let current_wave_done;
+        if self.current_wave == -1 {
+            current_wave_done = true;
+        } else {
+            if let Some(wave) = self.waves.get(self.current_wave as usize) {
+                current_wave_done = self.trigger_met(&wave.victory_condition);
+            } else {
+                current_wave_done = true;
+            }
+        }
+
+        if current_wave_done || self.spawn_wave_override {
+            self.spawn_wave_override = false;
+            if let Some(wave) = self.waves.get((self.current_wave + 1) as usize) {
+                if !self.wave_text_shown {
+                    events.push(SimEvent::WaveText(wave.ui_lines.clone()));
+                }
+
+                if self.frames_elapsed_since_last_wave > wave.frame_delay as _ {
+                    self.wave_stats = WaveStats::default();
+
+                    debug!("SPAWNING WAVE: {:?}", self.current_wave);
+                    self.frames_elapsed_since_last_wave = 0;
+                    self.current_wave += 1;
+                    if let Some(biome) = wave.biome {
+                        if biome != self.biome {
+                            self.biome = biome;
+                            self.grid_world.initial_terrain_setup(
+                                &mut self.random,
+                                &biome.terrain_config(),
+                                &self.defend_targets,
+                            );
+                            self.level = None;
+                        }
+                    }
+                    if let Some(path) = wave.level.clone() {
+                        self.use_level(&path);
+                    }
+                    // Borrowed again, laying the level needed all of `self`.
+                    let wave = &self.waves[self.current_wave as usize];
+                    let kind = self.enemy_kinds.find(&wave.enemy).unwrap_or_else(|| {
+                        warn!("Wave has an unknown enemy: {:?}", wave.enemy);
+                        EnemyKindId::ANT
+                    });
+                    let pattern = self.resolve_spawn_pattern(&wave.spawn);
+                    // The wave can override how fast and tough its kind of enemy is.
+                    let enemy = self.enemy_kinds.get(kind);
+                    let speed = wave.ant_speed.unwrap_or(enemy.speed);
+                    let health = wave.ant_health.unwrap_or((enemy.health, enemy.health));
+                    let wave_ants = spawn_ants_with_pattern(
+                        &mut self.random,
+                        self.world_size,
+                        kind,
+                        &pattern,
+                        wave.ant_count,
+                        speed,
+                        health,
+                    );
+                    if wave.spawn_frames == 0 {
+                        self.ants.extend(wave_ants);
+                    } else {
+                        self.spawn_queue.ants.extend(wave_ants);
+                        self.spawn_queue.per_tick =
+                            self.spawn_queue.ants.len() as f32 / wave.spawn_frames as f32;
+                    }
+                    self.rummage_refresh_timer = 0;
+
+                    // Remove everything but it may come back.
+                    for _ in 0..self.slider.targets.len() {
+                        self.slider.remove_random(&mut self.random);
+                    }
+
+                    for option in wave.rummage_options.iter() {
+                        self.slider
+                            .add_option(&mut self.random, option.len(), *option);
+                    }
+
+                    if wave.bonus_text.len() > 0 {
+                        events.push(SimEvent::BonusText(wave.bonus_text.clone(), 50.0));
+                    }
+
+                    let random_amount = self.random.range_u32(1..10);
+                    for _ in 0..random_amount {
+                        self.slider.insert_random_lost_entry(&mut self.random);
+                    }
+
+                    self.rummage_count = 2;
+
+                    self.wave_text_shown = false;
+
+                    events.push(SimEvent::WaveSpawned);
+                }
+            } else {
+                // No more waves!
+                self.victory = true;
+            }
+        }
+    }
+
+    fn update_spitballs(&mut self) -> Vec<usize> {
+        let mut to_despawn = Vec::new();
+        for (i, spitball) in self.spitballs.iter_mut().enumerate() {
+            let dir = (spitball.target - spitball.position.xy()).normalized();
+
+            let spitball_speed = 0.8;
+            spitball.position += (dir * spitball_speed).extend(0.0);
+            if (spitball.position.xy() - spitball.target).length() < 1.0 {
+                to_despawn.push(i);
+                debug!("REACHED DESTINATION!");
+                let spitball_material = &mut spitball.material;
+                self.grid_world
+                    .for_tile_in_radius(spitball.position.xy(), 3.0, |_, _, tile| {
+                        if let Some(material) = spitball_material.pop() {
+                            tile.height = tile.height.saturating_add(100);
+                            tile.material = material;
+                        }
+                        tile.kill_flag = true;
+                        true
+                    });
+            }
+        }
+        to_despawn
+    }
+
+    fn update_ants(&mut self, ants_to_despawn: &mut Vec<usize>, events: &mut Vec<SimEvent>) {
+        // Taken out so the rest of the simulation can be borrowed while an ant is updated.
+        let mut ants = std::mem::take(&mut self.ants);
+
+        let ants_seed = self.random.range_u32(0..u32::MAX) as u64;
+        let ant_update = self.ant_update;
+        let steering = self.steering();
+        let steer = |(i, ant): (usize, &mut Ant)| {
+            // Its own random numbers, so it doesn't matter which thread it's steered on.
+            let mut random = Random::new_with_seed(ants_seed.wrapping_add(i as u64));
+            let height_here = steering.height_here(ant);
+            steering.steer(i, ant, height_here, &mut random)
+        };
+
+        let height_speed_multipliers: Vec<f32> = match ant_update {
+            #[cfg(feature = "parallel")]
+            AntUpdate::Parallel => {
+                use rayon::prelude::*;
+                ants.par_iter_mut()
+                    .enumerate()
+                    .with_min_len(ANTS_PER_TASK)
+                    .map(steer)
+                    .collect()
+            }
+            _ => ants.iter_mut().enumerate().map(steer).collect(),
+        };
+
+        for ((i, ant), height_speed_multiplier) in
+            ants.iter_mut().enumerate().zip(height_speed_multipliers)
+        {
+            let will_die = self.ant_on_tile(ant, events);
+            self.finish_ant(
+                i,
+                ant,
+                will_die,
+                height_speed_multiplier,
+                ants_to_despawn,
+                events,
+            );
+        }
+
+        self.ants = ants;
+    }
+
+    /// What an ant does to the tile it's on, and whether that kills it.
+    fn ant_on_tile(&mut self, ant: &mut Ant, events: &mut Vec<SimEvent>) -> bool {
+        let kind = self.enemy_kinds.get(ant.kind);
+        let p = ant.position;
+
+        let mut will_die = false;
+
+        if kind.forages {
+            if let Some(tile_here) = self.grid_world.get_tile_mut(p.x, p.y) {
+                if ant.return_mode {
+                    tile_here.pheromones.food += 1.0;
+                    tile_here.height = tile_here.height.saturating_sub(1);
+                    tile_here.times_changed += 1;
+                } else {
+                    tile_here.pheromones.home += 1.0;
+                    tile_here.times_changed += 1;
+                }
+                if tile_here.height > 150 && self.random.f32() > 0.5 {
+                    tile_here.height = tile_here.height.saturating_sub(1);
+                }
+
+                // Consider dropping material
+                if ant.return_mode && p.y > self.world_size.y - 20.0 && self.random.f32() > 0.95 {
+                    if tile_here.material == TileMaterial::Earth {
+                        ant.return_mode = false;
+
+                        if let Some(carrying) = ant.carrying.take() {
+                            tile_here.material = carrying;
+                            //tile_here.height += 10;
+                        }
+                    }
+                }
+
+                // Consider picking up things.
+                if p.y < 180.0
+                    && ant.carrying.is_none()
+                    && kind.carries
+                    && self.materials.get(tile_here.material).carried
+                {
+                    if tile_here.material == TileMaterial::Nacho {
+                        self.wave_stats.crumbs_eaten += 1;
+                    }
+                    ant.return_mode = true;
+                    ant.carrying = Some(tile_here.material);
+                    tile_here.material = TileMaterial::Earth;
+                }
+
+                ant.health = ant
+                    .health
+                    .saturating_sub(self.materials.get(tile_here.material).damage_per_tick);
+            }
+        }
+
+        if let Some(&tile_here) = self.grid_world.get_tile(p.x, p.y) {
+            if (kind.killed_by_kill_flag && tile_here.kill_flag)
+                || (kind.killed_by_kill_ants_only && tile_here.kill_ants_only)
+            {
+                will_die = true;
+
+                // Killed violently, scatter guts.
+                self.grid_world
+                    .for_tile_in_radius(p.xy(), 2.0, |_tile_p, _, t| {
+                        if self.random.f32() > 0.8 {
+                            t.material = TileMaterial::AntGuts;
+                            t.height = t.height.saturating_add(5);
+                        }
+                        false
+                    });
+
+                events.push(SimEvent::AntKilled);
+            }
+
+            if let Some(radius) = kind.kills_ants_in_radius {
+                self.grid_world
+                    .for_tile_in_radius(p.xy(), radius, |_tile_p, _, t| {
+                        t.kill_ants_only = true;
+                        true
+                    });
+            }
+        }
+
+        will_die
+    }
+
+    /// Split from `random` so serial updates can share it while steering.
+    fn steering(&self) -> Steering<'_> {
+        Steering {
+            world_size: self.world_size,
+            tiles: self.grid_world.view(),
+            enemy_kinds: &self.enemy_kinds,
+            materials: &self.materials,
+            ant_hash: &self.ant_hash,
+            prey_hash: &self.prey_hash,
+            flow_field: &self.flow_field,
+        }
+    }
+
+    /// Hurts the player if the ant got to a target, handles it dying and moves it.
+    fn finish_ant(
+        &mut self,
+        i: usize,
+        ant: &mut Ant,
+        mut will_die: bool,
+        height_speed_multiplier: f32,
+        ants_to_despawn: &mut Vec<usize>,
+        events: &mut Vec<SimEvent>,
+    ) {
+        let kind = self.enemy_kinds.get(ant.kind);
+        let p = ant.position;
+
+        let mut speed_multiplier = 1.0;
+        if ant.return_mode {
+            speed_multiplier *= 1.5
+        }
+
+        if ant.position.y > self.world_size.y - 1.0 {
+            ant.dir = -Vec2::Y;
+        }
+
+        if ant.health < 100 {
+            if self.random.f32() > 0.8 {
+                ant.health = ant.health.saturating_sub(1)
+            }
+        }
+
+        if ant.health == 0 {
+            will_die = true;
+        }
+
+        if !self.sandbox_mode
+            && !self.victory
+            && self.player_health > 0
+            && self
+                .defend_targets
+                .iter()
+                .any(|target| target.reached_by(ant.position.xy()))
+        {
+            // Hurt player
+            self.player_health -= 1;
+            self.wave_stats.ants_reached_feet += 1;
+            events.push(SimEvent::AntReachedFeet);
+
+            debug!("ANT GOT PASSED: {:?}", self.player_health);
+
+            ants_to_despawn.push(i);
+        }
+
+        if will_die {
+            ants_to_despawn.push(i);
+            self.wave_stats.ants_killed += 1;
+
+            self.grid_world
+                .for_tile_in_radius(p.xy(), 1.0, |_tile_p, _, t| {
+                    if self.random.f32() > 0.4 {
+                        t.material = TileMaterial::AntBody;
+                        t.height = 155;
+                    }
+                    false
+                });
+
+            // Ant death pheromones to attract more ants.
+            let ant_pheremone_radius = 10.0;
+            self.grid_world.for_tile_in_radius(
+                p.xy(),
+                ant_pheremone_radius,
+                |_tile_p, radius, t| {
+                    t.pheromones.alarm += 5.0 * (1.0 - (radius / ant_pheremone_radius));
+                    false
+                },
+            );
+
+            // Drop what it's carrying if it dies.
+            if let Some(tile_here) = self.grid_world.get_tile_mut(p.x, p.y) {
+                if let Some(carrying) = ant.carrying.take() {
+                    tile_here.material = carrying;
+                }
+            }
+        }
+
+        let health_speed_multiplier = ant.health as f32 / 255.0;
+
+        speed_multiplier *= kind.speed_multiplier;
+
+        ant.position += ant.dir.extend(0.0)
+            * ant.speed
+            * speed_multiplier
+            * height_speed_multiplier
+            * health_speed_multiplier;
+    }
+
+    /// Advance the game by one fixed tick.
+    pub fn step(&mut self, input: &TickInput) -> Vec<SimEvent> {
+        // There's no way to read where a [Random] is up to, so every tick starts it afresh.
+        // That way a save only needs `seed` and `tick` to carry on the same.
+        self.random = Random::new_with_seed(tick_seed(self.seed, self.tick));
+        self.tick += 1;
+
+        let mut events = Vec::new();
+
+        for command in input.commands.iter() {
+            self.apply_command(*command, &mut events);
+        }
+
+        let mut ants_to_despawn: Vec<usize> = Vec::new();
+
+        self.slider.progress_line();
+
+        if self.player_health > 0 && !self.sandbox_mode && !self.victory && self.started_once {
+            self.update_waves(&mut events);
+        }
+
+        if !self.spawn_queue.ants.is_empty() {
+            self.spawn_queue.accumulated += self.spawn_queue.per_tick;
+            while self.spawn_queue.accumulated >= 1.0 {
+                self.spawn_queue.accumulated -= 1.0;
+                if let Some(ant) = self.spawn_queue.ants.pop_front() {
+                    self.ants.push(ant);
+                }
+            }
+            if self.spawn_queue.ants.is_empty() {
+                self.spawn_queue.accumulated = 0.0;
+            }
+        }
+
+        self.ant_hash.rebuild(
+            self.ants
+                .iter()
+                .enumerate()
+                .map(|(i, ant)| (i, ant.position.xy())),
+        );
+        let enemy_kinds = &self.enemy_kinds;
+        self.prey_hash.rebuild(
+            self.ants
+                .iter()
+                .enumerate()
+                .filter(|(_, ant)| enemy_kinds.get(ant.kind).hunts_in_radius.is_none())
+                .map(|(i, ant)| (i, ant.position.xy())),
+        );
+
+        if self
+            .ants
+            .iter()
+            .any(|ant| self.enemy_kinds.uses_flow_field(ant.kind))
+        {
+            self.flow_field
+                .update(&mut self.grid_world, &self.materials, &self.defend_targets);
+        }
+
+        let pointer_position_new = input.pointer;
+        let max_pointer_speed = self.pointer_max_speed.unwrap_or(100.0);
+
+        // Adjust pointer speed
+        {
+            let dir = pointer_position_new - self.pointer_position;
+            let length = dir.length();
+
+            let max_scale = (length / 200.0).clamp(0.0, 1.0);
+
+            let amount = max_scale * max_pointer_speed;
+
+            if length != 0.0 {
+                let dir = dir / length;
+                self.pointer_position += dir * amount;
+            }
+        }
+
+        if self.player_health > 0 || self.sandbox_mode {
+            let mut context = ItemContext {
+                input,
+                ants_to_despawn: &mut ants_to_despawn,
+                events: &mut events,
+            };
+            items::update_item(self, &mut context);
+        }
+
+        let spitballs_to_despawn = self.update_spitballs();
+
+        self.update_ants(&mut ants_to_despawn, &mut events);
+
+        self.grid_world.update_pheromones(&self.materials);
+        self.grid_world
+            .update_tiles(&mut self.random, &self.materials);
+        self.pointer_last_position = self.pointer_position;
+
+        if !spitballs_to_despawn.is_empty() {
+            let mut i = 0;
+            self.spitballs.retain(|_| {
+                i += 1;
+                !spitballs_to_despawn.contains(&(i - 1))
+            });
+        }
+
+        if !ants_to_despawn.is_empty() {
+            ants_to_despawn.sort_unstable();
+            ants_to_despawn.dedup();
+            let mut i = 0;
+            self.ants.retain(|_| {
+                i += 1;
+                ants_to_despawn.binary_search(&(i - 1)).is_err()
+            });
+        }
+
+        if input.pointer_held {
+            self.pointer_held_len += 1;
+        } else {
+            self.pointer_held_len = 0;
+        }
+
+        if self.is_game_over() && !self.defeated {
+            self.defeated = true;
+            events.push(SimEvent::Defeated);
+        }
+
+        if self.save_requested {
+            self.save_requested = false;
+            events.push(SimEvent::SaveRequested);
+        }
+
+        events
+    }
+}
+
+/// What ants need to pick where to go. It only reads, so ants can be steered on several threads.
+struct Steering<'a> {
+    world_size: Vec2,
+    tiles: TileView<'a>,
+    enemy_kinds: &'a EnemyRegistry,
+    materials: &'a MaterialRegistry,
+    ant_hash: &'a SpatialHash,
+    prey_hash: &'a SpatialHash,
+    flow_field: &'a FlowField,
+}
+
+impl Steering<'_> {
+    /// The height of the tile the ant's on at the start of the tick, which it compares
+    /// the tiles around it to. Only foraging enemies look at heights.
+    fn height_here(&self, ant: &Ant) -> u8 {
+        if !self.enemy_kinds.get(ant.kind).forages {
+            return 0;
+        }
+        self.tiles
+            .get_tile(ant.position.x, ant.position.y)
+            .map_or(0, |t| t.height)
+    }
+
+    /// Points ant `i` at the best tile around it, then at its prey and away from the crowd.
+    /// Returns how much the slope it's heading up slows it down.
+    fn steer(&self, i: usize, ant: &mut Ant, height_here: u8, random: &mut Random) -> f32 {
+        let kind = self.enemy_kinds.get(ant.kind);
+        let p = ant.position;
+
+        // Heading back there's nowhere to go in the flow field,
+        // and walled in ants can only go by their usual scoring.
+        let flow_field_weight =
+            if ant.return_mode || self.flow_field.distance_at(p.xy()) == UNREACHABLE {
+                0.0
+            } else {
+                kind.flow_field_weight
+            };
+
+        // The ant moves towards the tile with the lowest score.
+        let mut lowest_score = i32::MAX;
+
+        let mut lowest_score_tile = None;
+        let mut height_speed_multiplier = 1.0;
+
+        let scoring_timer = profile::timer(Section::Scoring);
+        self.tiles.for_tile_in_radius(p.xy(), 4.0, |tile_p, _, t| {
+            let alignment = (p.xy() - tile_p).dot(ant.dir);
+            let alignment_score_adjust = if alignment > 0.0 { 300 } else { 0 };
+
+            let height_diff = t.height as f32 - height_here as f32;
+
+            let height_score_adjust = if height_diff < 50.0 {
+                // Next tile is lower
+                (height_diff.abs() / 255.0) * 100.0
+            } else {
+                // Next tile is greater
+                (height_diff.abs() / 255.0) * 1000.0
+            };
+
+            // Heading out ants follow food and alarm scent and avoid where they've been.
+            // Heading back they follow the home trail and keep clear of trouble.
+            let pheremone_score = if ant.return_mode {
+                -t.pheromones.home + t.pheromones.alarm
+            } else {
+                t.pheromones.home - t.pheromones.food - t.pheromones.alarm
+            } as i32;
+
+            let mut tile_score =
+                pheremone_score + height_score_adjust as i32 + alignment_score_adjust;
+
+            // Random score adjust
+            // I believe this biases towards right tiles.
+            // tile_score += random.range_i32(0..4);
+
+            let Some(walk_cost) = self.materials.get(t.material).walk_cost else {
+                return;
+            };
+            tile_score += walk_cost;
+
+            if flow_field_weight > 0.0 {
+                let flow_distance = self.flow_field.distance_at(tile_p);
+                if flow_distance == UNREACHABLE {
+                    return;
+                }
+                tile_score = ((1.0 - flow_field_weight) * tile_score as f32
+                    + flow_field_weight * flow_distance as f32) as i32;
+            }
+
+            if tile_p.y > self.world_size.y - 5.0 {
+                tile_score = i32::MAX;
+            }
+
+            tile_score = (kind.score)(ant, tile_p, t, tile_score);
+
+            let height_veto = t.height > 230 && random.f32() > 0.01;
+
+            if !height_veto && tile_score < lowest_score {
+                lowest_score = tile_score;
+                lowest_score_tile = Some(tile_p);
+
+                height_speed_multiplier =
+                    (1.0 - (height_score_adjust / 20.0).min(1.0)).clamp(0.5, 1.0);
+            }
+        });
+        drop(scoring_timer);
+
+        if let Some(lowest_score_tile) = lowest_score_tile {
+            let tile_p = p.xy().as_i32().as_f32();
+
+            let unnormalized_dir = lowest_score_tile - tile_p.xy();
+            let len = unnormalized_dir.length();
+
+            if len != 0.0 {
+                let new_dir = unnormalized_dir / len;
+                ant.dir = new_dir;
+                ant.facing = ant.dir.y.atan2(ant.dir.x);
+            } else {
+                ant.dir = Vec2::ZERO;
+            }
+        } else {
+            ant.dir = Vec2::ZERO;
+        }
+
+        if let Some(radius) = kind.hunts_in_radius {
+            if let Some((_, prey)) = self.prey_hash.nearest(p.xy(), radius) {
+                let to_prey = prey - p.xy();
+                let len = to_prey.length();
+                if len != 0.0 {
+                    ant.dir = to_prey / len;
+                    ant.facing = ant.dir.y.atan2(ant.dir.x);
+                }
+            }
+        }
+
+        if kind.crowd_avoidance > 0.0 && ant.dir != Vec2::ZERO {
+            let mut away_from_crowd = Vec2::ZERO;
+            self.ant_hash
+                .for_each_in_radius(p.xy(), CROWD_RADIUS, |j, other| {
+                    let away = p.xy() - other;
+                    let distance = away.length();
+                    if j != i && distance != 0.0 {
+                        away_from_crowd += away / distance * (1.0 - distance / CROWD_RADIUS);
+                    }
+                });
+
+            let new_dir = ant.dir + away_from_crowd * kind.crowd_avoidance;
+            let len = new_dir.length();
+            if len != 0.0 {
+                ant.dir = new_dir / len;
+                ant.facing = ant.dir.y.atan2(ant.dir.x);
+            }
+        }
+
+        height_speed_multiplier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::DEFAULT_WORLD_SIZE;
+
+    const SEED: u64 = 52;
+
+    /// A world with a wave's worth of ants that don't hurt anyone or disappear.
+    fn swarm(ant_update: AntUpdate) -> Simulation {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        simulation.ant_update = ant_update;
+        simulation.sandbox_mode = true;
+        spawn_ants(
+            &mut simulation.ants,
+            &mut simulation.random,
+            DEFAULT_WORLD_SIZE,
+            500,
+        );
+        simulation
+    }
+
+    fn ant_at(x: f32, y: f32) -> Ant {
+        Ant {
+            position: Vec3::new(x, y, 0.0),
+            facing: 0.0,
+            dir: -Vec2::Y,
+            speed: 1.0,
+            return_mode: false,
+            health: 1,
+            carrying: None,
+            kind: EnemyKindId::ANT,
+            home: Vec2::new(x, y),
+        }
+    }
+
+    fn assert_ants_match(a: &Simulation, b: &Simulation, tick: usize) {
+        assert_eq!(a.ants.len(), b.ants.len(), "ant count on tick {}", tick);
+        for (i, (a, b)) in a.ants.iter().zip(b.ants.iter()).enumerate() {
+            assert_eq!(
+                a.position, b.position,
+                "ant {} position on tick {}",
+                i, tick
+            );
+            assert_eq!(a.dir, b.dir, "ant {} dir on tick {}", i, tick);
+            assert_eq!(a.health, b.health, "ant {} health on tick {}", i, tick);
+            assert_eq!(
+                a.carrying, b.carrying,
+                "ant {} carrying on tick {}",
+                i, tick
+            );
+        }
+    }
+
+    #[test]
+    fn same_seed_and_input_play_out_the_same() {
+        let mut a = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        let mut b = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        // So the test can't pass by nothing being random at all.
+        let mut other_seed = Simulation::new(DEFAULT_WORLD_SIZE, SEED + 1);
+
+        for tick in 0..600 {
+            let mut input = TickInput::default();
+            // Starts the game, then rummages now and again.
+            if tick % 120 == 0 {
+                input.commands.push(Command::Rummage { key_up: false });
+            }
+            a.step(&input);
+            b.step(&input);
+            other_seed.step(&input);
+            assert_ants_match(&a, &b, tick);
+            assert_eq!(a.player_health, b.player_health, "health on tick {}", tick);
+            assert_eq!(a.current_wave, b.current_wave, "wave on tick {}", tick);
+        }
+        assert!(!a.ants.is_empty());
+        assert!(
+            a.ants.len() != other_seed.ants.len()
+                || a.ants
+                    .iter()
+                    .zip(other_seed.ants.iter())
+                    .any(|(a, other)| a.position != other.position),
+            "a different seed played out the same"
+        );
+    }
+
+    #[test]
+    fn parallel_ants_match_serial() {
+        let mut serial = swarm(AntUpdate::Serial);
+        let mut parallel = swarm(AntUpdate::Parallel);
+
+        for tick in 0..120 {
+            serial.step(&TickInput::default());
+            parallel.step(&TickInput::default());
+            assert_ants_match(&serial, &parallel, tick);
+        }
+    }
+
+    #[test]
+    fn low_ant_count_counts_ants_still_to_spawn() {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        simulation.ants = vec![ant_at(10.0, 10.0)];
+        assert!(simulation.trigger_met(&WaveTrigger::LowAntCount(1)));
+        assert!(!simulation.trigger_met(&WaveTrigger::LowAntCount(0)));
+
+        simulation.spawn_queue.ants.push_back(ant_at(45.0, 190.0));
+        assert!(!simulation.trigger_met(&WaveTrigger::LowAntCount(1)));
+        assert!(simulation.trigger_met(&WaveTrigger::LowAntCount(2)));
+    }
+
+    #[test]
+    fn low_ant_count_away_from_spawn_leaves_out_ants_at_the_spawn() {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        simulation.waves = vec![Wave {
+            spawn: SpawnPattern::Point {
+                x: 45.0,
+                y: 190.0,
+                radius: 3.0,
+            },
+            ..Wave::default()
+        }];
+        simulation.current_wave = 0;
+        simulation.ants = vec![
+            ant_at(45.0, 190.0),
+            ant_at(50.0, 185.0),
+            ant_at(45.0, 100.0),
+        ];
+        let trigger = WaveTrigger::LowAntCountAwayFromSpawn;
+        assert!(simulation.trigger_met(&trigger(1)));
+        assert!(!simulation.trigger_met(&trigger(0)));
+
+        simulation.ants.pop();
+        assert!(simulation.trigger_met(&trigger(0)));
+
+        // Ants that haven't spawned yet haven't gone anywhere, but still count.
+        simulation.spawn_queue.ants.push_back(ant_at(45.0, 190.0));
+        assert!(!simulation.trigger_met(&trigger(0)));
+    }
+
+    #[test]
+    fn material_tiles_above_is_counted_now_and_again() {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        let trigger = WaveTrigger::MaterialTilesAbove(TileMaterial::Nacho, 10);
+        simulation.frames_elapsed_since_last_wave = 0;
+        assert!(!simulation.trigger_met(&trigger));
+
+        simulation
+            .grid_world
+            .for_tile_in_radius(Vec2::new(45.0, 100.0), 3.0, |_, _, t| {
+                t.material = TileMaterial::Nacho;
+                false
+            });
+        assert!(simulation.trigger_met(&trigger));
+        simulation.frames_elapsed_since_last_wave = 1;
+        assert!(!simulation.trigger_met(&trigger));
+        simulation.frames_elapsed_since_last_wave = MATERIAL_COUNT_INTERVAL;
+        assert!(simulation.trigger_met(&trigger));
+    }
+
+    #[test]
+    fn all_and_any_combine_triggers() {
+        let mut simulation = Simulation::new(DEFAULT_WORLD_SIZE, SEED);
+        simulation.frames_elapsed_since_last_wave = 100;
+        simulation.wave_stats.ants_killed = 5;
+        let met = || WaveTrigger::FramesElapsedSinceLast(100);
+        let also_met = || WaveTrigger::AntsKilled(5);
+        let unmet = || WaveTrigger::AntsKilled(6);
+
+        assert!(simulation.trigger_met(&WaveTrigger::All(vec![met(), also_met()])));
+        assert!(!simulation.trigger_met(&WaveTrigger::All(vec![met(), unmet()])));
+        assert!(simulation.trigger_met(&WaveTrigger::Any(vec![unmet(), met()])));
+        assert!(!simulation.trigger_met(&WaveTrigger::Any(vec![unmet()])));
+        assert!(simulation.trigger_met(&WaveTrigger::Any(vec![
+            unmet(),
+            WaveTrigger::All(vec![met(), also_met()]),
+        ])));
+    }
+}
@@ -0,0 +1,154 @@
+use koi3::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SliderOption {
+    Gun,
+    Bullets,
+    Nachos,
+    Toothpaste,
+}
+
+impl SliderOption {
+    /// How much of the slider this option takes up.
+    pub fn len(&self) -> f32 {
+        match self {
+            SliderOption::Gun => 0.3,
+            SliderOption::Bullets => 0.02,
+            SliderOption::Nachos => 0.02,
+            SliderOption::Toothpaste => 0.15,
+        }
+    }
+}
+
+/// The rummage slider. This only tracks the gameplay state,
+/// the entities that display it are owned by the renderer.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Slider {
+    pub line_position: f32,
+    pub line_dir: f32,
+    pub targets: Vec<((f32, f32), SliderOption)>,
+    pub visible: bool,
+    pub lost_entries: Vec<(f32, SliderOption)>,
+}
+
+fn ranges_overlap(a: (f32, f32), b: (f32, f32)) -> bool {
+    let (a_start, a_end) = a;
+    let (b_start, b_end) = b;
+
+    // Check if the ranges overlap
+    a_start <= b_end && b_start <= a_end
+}
+
+impl Slider {
+    pub fn new() -> Self {
+        Self {
+            line_position: 0.0,
+            line_dir: 1.0,
+            targets: Vec::new(),
+            visible: true,
+            lost_entries: Vec::new(),
+        }
+    }
+
+    pub fn add_option(&mut self, random: &mut Random, len: f32, option: SliderOption) {
+        let mut start = None;
+        // Try 20 times to insert
+        for _ in 0..20 {
+            let mut overlaps = false;
+            let proposed_start = random.f32();
+
+            if proposed_start + len > 1.0 {
+                continue;
+            }
+
+            for t in self.targets.iter() {
+                let overlaps_here = ranges_overlap((proposed_start, proposed_start + len), t.0);
+                if overlaps_here {
+                    overlaps = true;
+                    break;
+                }
+            }
+
+            if !overlaps {
+                start = Some(proposed_start);
+                break;
+            }
+        }
+
+        if let Some(start) = start {
+            self.targets.push(((start, start + len), option));
+        } else {
+            self.lost_entries.push((len, option));
+        }
+    }
+
+    pub fn progress_line(&mut self) {
+        self.line_position += self.line_dir * 0.01;
+
+        if self.line_position > 1.0 {
+            self.line_position = 1.0;
+            self.line_dir = -1.0;
+        }
+
+        if self.line_position <= 0.0 {
+            self.line_position = 0.0;
+            self.line_dir = 1.0;
+        }
+    }
+
+    pub fn insert_random_lost_entry(&mut self, random: &mut Random) {
+        if !self.lost_entries.is_empty() {
+            let i = random.range_u32(0..self.lost_entries.len() as _);
+            let removed = self.lost_entries.remove(i as _);
+            self.add_option(random, removed.0, removed.1);
+        }
+    }
+
+    pub fn remove_random(&mut self, random: &mut Random) {
+        if !self.targets.is_empty() {
+            let i = random.range_u32(0..self.targets.len() as _);
+            let removed = self.targets.remove(i as _);
+            self.lost_entries
+                .push((removed.0 .1 - removed.0 .0, removed.1));
+        }
+    }
+
+    pub fn stop_line(&mut self) -> Option<SliderOption> {
+        if !self.visible {
+            return None;
+        }
+
+        let mut found_target = None;
+
+        for (i, t) in self.targets.iter().enumerate() {
+            let overlaps_here =
+                ranges_overlap((self.line_position - 0.02, self.line_position + 0.02), t.0);
+
+            if overlaps_here {
+                found_target = Some(i);
+                break;
+            }
+        }
+
+        if let Some(found_target) = found_target {
+            let removed = self.targets.remove(found_target);
+            Some(removed.1)
+        } else {
+            None
+        }
+    }
+
+    pub fn hide(&mut self) {
+        self.visible = false;
+    }
+
+    pub fn show(&mut self) {
+        self.visible = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.targets.clear();
+        self.lost_entries.clear();
+    }
+}
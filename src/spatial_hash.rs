@@ -0,0 +1,171 @@
+use koi3::*;
+
+/// A grid of buckets for finding things near a point without checking everything.
+/// It's rebuilt from scratch every tick, so it's a snapshot of where things were at the start of it.
+pub struct SpatialHash {
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    /// Where each cell's entries start in `entries`. The last element is `entries.len()`.
+    cell_starts: Vec<usize>,
+    entries: Vec<(usize, Vec2)>,
+    // Scratch space used while rebuilding.
+    cells_of_entries: Vec<usize>,
+    next_in_cell: Vec<usize>,
+    unsorted: Vec<(usize, Vec2)>,
+}
+
+impl SpatialHash {
+    pub fn new(world_size: Vec2, cell_size: f32) -> Self {
+        let width = (world_size.x / cell_size).ceil().max(1.0) as usize;
+        let height = (world_size.y / cell_size).ceil().max(1.0) as usize;
+        Self {
+            cell_size,
+            width,
+            height,
+            cell_starts: vec![0; width * height + 1],
+            entries: Vec::new(),
+            cells_of_entries: Vec::new(),
+            next_in_cell: Vec::new(),
+            unsorted: Vec::new(),
+        }
+    }
+
+    /// Anything outside the world is put in the nearest edge cell.
+    fn cell(&self, x: i32, y: i32) -> usize {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        x * self.height + y
+    }
+
+    fn cell_coordinates(&self, p: Vec2) -> (i32, i32) {
+        (
+            (p.x / self.cell_size).floor() as i32,
+            (p.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn rebuild(&mut self, items: impl Iterator<Item = (usize, Vec2)>) {
+        self.unsorted.clear();
+        self.unsorted.extend(items);
+
+        // Count how many entries go in each cell, then sort them into place.
+        self.cells_of_entries.clear();
+        self.cell_starts.iter_mut().for_each(|c| *c = 0);
+        for (_, p) in self.unsorted.iter() {
+            let (x, y) = self.cell_coordinates(*p);
+            let cell = self.cell(x, y);
+            self.cells_of_entries.push(cell);
+            self.cell_starts[cell + 1] += 1;
+        }
+
+        for i in 1..self.cell_starts.len() {
+            self.cell_starts[i] += self.cell_starts[i - 1];
+        }
+
+        self.next_in_cell.clear();
+        self.next_in_cell.extend_from_slice(&self.cell_starts);
+        self.entries.clear();
+        self.entries.resize(self.unsorted.len(), (0, Vec2::ZERO));
+        for (entry, cell) in self.unsorted.iter().zip(self.cells_of_entries.iter()) {
+            self.entries[self.next_in_cell[*cell]] = *entry;
+            self.next_in_cell[*cell] += 1;
+        }
+    }
+
+    /// Calls `f` with the index and position of everything within `radius` of `center`.
+    pub fn for_each_in_radius(&self, center: Vec2, radius: f32, mut f: impl FnMut(usize, Vec2)) {
+        let (min_x, min_y) = self.cell_coordinates(center - Vec2::fill(radius));
+        let (max_x, max_y) = self.cell_coordinates(center + Vec2::fill(radius));
+
+        // Clamped the same way as when things were added, so things outside the world are found.
+        let min_x = min_x.clamp(0, self.width as i32 - 1);
+        let min_y = min_y.clamp(0, self.height as i32 - 1);
+        let max_x = max_x.clamp(0, self.width as i32 - 1);
+        let max_y = max_y.clamp(0, self.height as i32 - 1);
+
+        let radius_squared = radius * radius;
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let cell = self.cell(x, y);
+                for (i, p) in &self.entries[self.cell_starts[cell]..self.cell_starts[cell + 1]] {
+                    if (*p - center).length_squared() <= radius_squared {
+                        f(*i, *p);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The closest thing within `radius` of `center`.
+    pub fn nearest(&self, center: Vec2, radius: f32) -> Option<(usize, Vec2)> {
+        let mut closest = None;
+        let mut closest_distance = f32::MAX;
+        self.for_each_in_radius(center, radius, |i, p| {
+            let distance = (p - center).length_squared();
+            if distance < closest_distance {
+                closest_distance = distance;
+                closest = Some((i, p));
+            }
+        });
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_same_as_checking_everything() {
+        let world_size = Vec2::new(64.0, 48.0);
+        let mut random = Random::new_with_seed(8);
+        // Some of these are past the edges of the world, like things that have wandered off it.
+        let points: Vec<Vec2> = (0..500)
+            .map(|_| Vec2::new(random.range_f32(-10.0..74.0), random.range_f32(-10.0..58.0)))
+            .collect();
+
+        let mut spatial_hash = SpatialHash::new(world_size, 6.0);
+        spatial_hash.rebuild(points.iter().copied().enumerate());
+
+        let centers = [
+            Vec2::new(-5.0, -5.0),
+            Vec2::new(70.0, 20.0),
+            Vec2::new(30.0, 55.0),
+        ];
+        let centers =
+            centers
+                .into_iter()
+                .chain((0..100).map(|_| {
+                    Vec2::new(random.range_f32(-8.0..72.0), random.range_f32(-8.0..56.0))
+                }));
+        for center in centers {
+            for radius in [0.5, 4.0, 13.0] {
+                let mut found = Vec::new();
+                spatial_hash.for_each_in_radius(center, radius, |i, p| {
+                    assert_eq!(p, points[i]);
+                    found.push(i);
+                });
+                found.sort_unstable();
+
+                let expected: Vec<usize> = (0..points.len())
+                    .filter(|i| (points[*i] - center).length_squared() <= radius * radius)
+                    .collect();
+                assert_eq!(found, expected, "near {:?} within {}", center, radius);
+
+                let nearest = spatial_hash
+                    .nearest(center, radius)
+                    .map(|(_, p)| (p - center).length());
+                let expected_nearest = expected
+                    .iter()
+                    .map(|i| (points[*i] - center).length())
+                    .reduce(f32::min);
+                assert_eq!(
+                    nearest, expected_nearest,
+                    "near {:?} within {}",
+                    center, radius
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::enemies::EnemyRegistry;
+use crate::grid_world::{Biome, TileMaterial};
+use crate::slider::SliderOption;
+use crate::warn;
+
+/// The waves file the game ships with, built in so it still plays the same without it.
+const BUILT_IN: &str = include_str!("../assets/waves.ron");
+
+/// When the current wave is over and the next one can start.
+/// Counts are since the current wave spawned.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WaveTrigger {
+    /// At most this many ants are left.
+    LowAntCount(usize),
+    /// Like `LowAntCount` but ants still sitting where the wave spawned them aren't counted.
+    LowAntCountAwayFromSpawn(usize),
+    FramesElapsedSinceLast(u32),
+    /// At least this many ants died.
+    AntsKilled(u32),
+    /// At least this many ants got to the player's feet,
+    /// or any other [DefendTarget](crate::defend::DefendTarget).
+    AntsReachedFeet(u32),
+    /// At least this many chip crumbs were carried off by ants.
+    CrumbsEaten(u32),
+    /// The player's health dropped below this.
+    PlayerHealthBelow(i32),
+    /// More than this many tiles are the material.
+    /// They're only counted every half a second, so it can be met that much late.
+    MaterialTilesAbove(TileMaterial, usize),
+    /// Every one of these has been met.
+    All(Vec<WaveTrigger>),
+    /// Any one of these has been met.
+    Any(Vec<WaveTrigger>),
+}
+
+impl WaveTrigger {
+    /// The ants are dealt with. Once a wave has gone on for `move_on_after` frames
+    /// ants that never left where they spawned don't hold up the next wave.
+    pub fn ants_cleared(move_on_after: u32) -> Self {
+        WaveTrigger::Any(vec![
+            WaveTrigger::LowAntCount(0),
+            WaveTrigger::All(vec![
+                WaveTrigger::FramesElapsedSinceLast(move_on_after),
+                WaveTrigger::LowAntCountAwayFromSpawn(0),
+            ]),
+        ])
+    }
+}
+
+/// How long a wave goes on before stragglers where it spawned stop holding it up.
+pub const DEFAULT_MOVE_ON_FRAMES: u32 = 60 * 30;
+
+/// What's happened since the current wave spawned, for [WaveTrigger]s.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct WaveStats {
+    pub ants_killed: u32,
+    pub ants_reached_feet: u32,
+    pub crumbs_eaten: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Where a wave's ants appear, in world coordinates.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SpawnPattern {
+    /// Lined up evenly along an edge of the world.
+    Edge(Edge),
+    /// Bunched up within `radius` of a point.
+    Point { x: f32, y: f32, radius: f32 },
+    /// Anywhere in a rectangle.
+    Scatter { min: (f32, f32), max: (f32, f32) },
+    /// Split evenly between several holes, each ant within `radius` of its hole.
+    Burrows { holes: Vec<(f32, f32)>, radius: f32 },
+    /// Like `Burrows` with the level's spawn points as the holes.
+    LevelSpawns { radius: f32 },
+}
+
+impl SpawnPattern {
+    /// Checks the numbers make sense, so a typo doesn't spawn ants nowhere.
+    /// Positions off the world are fine, the ants are moved onto it when they spawn.
+    pub fn check(&self) -> Result<(), String> {
+        let check_radius = |radius: f32| {
+            if radius.is_finite() && radius >= 0.0 {
+                Ok(())
+            } else {
+                Err(format!("radius must be zero or more, not {}", radius))
+            }
+        };
+        let check_point = |(x, y): (f32, f32)| {
+            if x.is_finite() && y.is_finite() {
+                Ok(())
+            } else {
+                Err(format!("({}, {}) isn't a position", x, y))
+            }
+        };
+
+        match self {
+            SpawnPattern::Edge(_) => Ok(()),
+            SpawnPattern::Point { x, y, radius } => {
+                check_point((*x, *y))?;
+                check_radius(*radius)
+            }
+            SpawnPattern::Scatter { min, max } => {
+                check_point(*min)?;
+                check_point(*max)?;
+                if min.0 > max.0 || min.1 > max.1 {
+                    return Err(format!("min {:?} is past max {:?}", min, max));
+                }
+                Ok(())
+            }
+            SpawnPattern::Burrows { holes, radius } => {
+                if holes.is_empty() {
+                    return Err("needs at least one hole".into());
+                }
+                for hole in holes {
+                    check_point(*hole)?;
+                }
+                check_radius(*radius)
+            }
+            SpawnPattern::LevelSpawns { radius } => check_radius(*radius),
+        }
+    }
+}
+
+/// Fields left out of a wave file take their value from [Wave::default].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Wave {
+    pub victory_condition: WaveTrigger,
+    pub frame_delay: usize,
+    pub ant_count: usize,
+    pub ui_lines: Vec<String>,
+    pub bonus_text: String,
+    pub rummage_options: Vec<SliderOption>,
+    /// The name of the [EnemyKind](crate::enemies::EnemyKind) the wave is made of.
+    pub enemy: String,
+    pub spawn: SpawnPattern,
+    /// Spawn the ants bit by bit over this many frames instead of all at once.
+    pub spawn_frames: u32,
+    /// The range each ant's speed is picked from, the enemy kind's `speed` if `None`.
+    pub ant_speed: Option<(f32, f32)>,
+    /// The range each ant's health is picked from, the enemy kind's `health` if `None`.
+    pub ant_health: Option<(u8, u8)>,
+    /// Regenerates the terrain as this biome when the wave starts, if it isn't already.
+    pub biome: Option<Biome>,
+    /// Lays this level file over the terrain when the wave starts, if it isn't already.
+    /// Applied after `biome`, and only if it was made for the same world size.
+    pub level: Option<String>,
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Self {
+            victory_condition: WaveTrigger::ants_cleared(DEFAULT_MOVE_ON_FRAMES),
+            frame_delay: 0,
+            ant_count: 10,
+            ui_lines: Vec::new(),
+            rummage_options: Vec::new(),
+            bonus_text: String::new(),
+            enemy: "ant".into(),
+            spawn: SpawnPattern::Edge(Edge::Top),
+            spawn_frames: 0,
+            ant_speed: None,
+            ant_health: None,
+            biome: None,
+            level: None,
+        }
+    }
+}
+
+/// The campaign the game ships with, the built in copy of `assets/waves.ron`.
+pub fn default_waves() -> Vec<Wave> {
+    parse_waves(BUILT_IN).expect("the built in waves.ron should load")
+}
+
+#[derive(Debug)]
+pub enum WaveLoadError {
+    Io(std::io::Error),
+    /// The file isn't valid RON.
+    Syntax(ron::error::SpannedError),
+    /// The file parsed but a wave in it is wrong.
+    Wave {
+        /// `None` if the problem is with the list itself rather than one wave.
+        index: Option<usize>,
+        field: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for WaveLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaveLoadError::Io(e) => write!(f, "{}", e),
+            WaveLoadError::Syntax(e) => write!(f, "{}", e),
+            WaveLoadError::Wave {
+                index: Some(index),
+                field,
+                message,
+            } if !field.is_empty() => write!(f, "wave {}, field `{}`: {}", index, field, message),
+            WaveLoadError::Wave {
+                index: Some(index),
+                message,
+                ..
+            } => write!(f, "wave {}: {}", index, message),
+            WaveLoadError::Wave { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for WaveLoadError {}
+
+/// Parses a RON list of waves, for example:
+///
+/// ```ron
+/// [
+///     (
+///         victory_condition: Any([AntsKilled(50), FramesElapsedSinceLast(5400)]),
+///         ant_count: 5,
+///         ui_lines: ["WAVE 1"],
+///         rummage_options: [Gun, Nachos],
+///         spawn: Burrows(holes: [(20.0, 190.0), (70.0, 190.0)], radius: 3.0),
+///         spawn_frames: 120,
+///     ),
+/// ]
+/// ```
+pub fn parse_waves(source: &str) -> Result<Vec<Wave>, WaveLoadError> {
+    let mut deserializer = ron::Deserializer::from_str(source).map_err(WaveLoadError::Syntax)?;
+
+    let waves: Vec<Wave> = match serde_path_to_error::deserialize(&mut deserializer) {
+        Ok(waves) => waves,
+        Err(e) => {
+            let mut index = None;
+            let mut field = String::new();
+            for segment in e.path().iter() {
+                match segment {
+                    serde_path_to_error::Segment::Seq { index: i } if index.is_none() => {
+                        index = Some(*i)
+                    }
+                    serde_path_to_error::Segment::Seq { .. } => field += &segment.to_string(),
+                    segment => {
+                        if !field.is_empty() {
+                            field.push('.');
+                        }
+                        field += &segment.to_string();
+                    }
+                }
+            }
+
+            let error = e.into_inner();
+            let message = deserializer.span_error(error).to_string();
+            return Err(WaveLoadError::Wave {
+                index,
+                field,
+                message,
+            });
+        }
+    };
+
+    deserializer
+        .end()
+        .map_err(|e| WaveLoadError::Syntax(deserializer.span_error(e)))?;
+
+    check_waves(&waves)?;
+    Ok(waves)
+}
+
+/// Errors if a wave is made of a kind of enemy that doesn't exist or spawns them nowhere.
+/// [parse_waves] already checks this.
+pub fn check_waves(waves: &[Wave]) -> Result<(), WaveLoadError> {
+    let enemy_kinds = EnemyRegistry::new();
+    for (index, wave) in waves.iter().enumerate() {
+        if enemy_kinds.find(&wave.enemy).is_none() {
+            return Err(WaveLoadError::Wave {
+                index: Some(index),
+                field: "enemy".into(),
+                message: format!("there's no kind of enemy called {:?}", wave.enemy),
+            });
+        }
+        wave.spawn.check().map_err(|message| WaveLoadError::Wave {
+            index: Some(index),
+            field: "spawn".into(),
+            message,
+        })?;
+    }
+    Ok(())
+}
+
+pub fn load_waves(path: impl AsRef<Path>) -> Result<Vec<Wave>, WaveLoadError> {
+    let source = std::fs::read_to_string(path).map_err(WaveLoadError::Io)?;
+    parse_waves(&source)
+}
+
+/// A wave file that's reloaded whenever it changes on disk.
+pub struct WaveFile {
+    pub path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl WaveFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            last_modified: modified_time(&path),
+            path,
+        }
+    }
+
+    /// Loads the waves, falling back to [default_waves] if the file can't be used.
+    pub fn load_or_default(&self) -> Vec<Wave> {
+        match load_waves(&self.path) {
+            Ok(waves) => waves,
+            Err(e) => {
+                warn!("Could not load waves from {:?}: {}", self.path, e);
+                warn!("Using the built in waves");
+                default_waves()
+            }
+        }
+    }
+
+    /// Returns the new waves if the file changed since it was last checked.
+    pub fn reload_if_changed(&mut self) -> Option<Result<Vec<Wave>, WaveLoadError>> {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+        Some(load_waves(&self.path))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave_error(source: &str) -> (Option<usize>, String) {
+        match parse_waves(source) {
+            Err(WaveLoadError::Wave { index, field, .. }) => (index, field),
+            other => panic!("expected a wave error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn built_in_waves_load() {
+        let waves = default_waves();
+        assert!(!waves.is_empty());
+        assert_eq!(waves[0].ant_count, 5);
+    }
+
+    #[test]
+    fn errors_name_the_wave_and_field() {
+        let source = "[(ant_count: 5), (ant_count: 6), (ant_count: \"seven\")]";
+        assert_eq!(wave_error(source), (Some(2), "ant_count".into()));
+
+        let source = "[(), (ui_lines: [\"WAVE 2\", 2])]";
+        assert_eq!(wave_error(source), (Some(1), "ui_lines[1]".into()));
+
+        let source = "[(), (enemy: \"wasp\")]";
+        assert_eq!(wave_error(source), (Some(1), "enemy".into()));
+    }
+
+    #[test]
+    fn bad_spawn_patterns_are_rejected() {
+        for spawn in [
+            "Point(x: 1.0, y: 2.0, radius: -1.0)",
+            "Scatter(min: (10.0, 0.0), max: (0.0, 10.0))",
+            "Burrows(holes: [], radius: 3.0)",
+            "LevelSpawns(radius: inf)",
+        ] {
+            let source = format!("[(), (), (spawn: {})]", spawn);
+            assert_eq!(wave_error(&source), (Some(2), "spawn".into()), "{}", spawn);
+        }
+
+        let source = "[(spawn: Burrows(holes: [(20.0, 190.0)], radius: 3.0))]";
+        assert!(parse_waves(source).is_ok());
+    }
+}